@@ -0,0 +1,173 @@
+//! The onion services' addresses once arti runs: found through [`crate::discovery`], cached in
+//! the state store across restarts, and compared with earlier runs to notice a changed identity.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::time::{Duration, Instant};
+use tracing::{info_span, warn, Instrument};
+
+use crate::discovery::{
+    discover_onion_address, discovery_strategies, onion_public_key, read_onion_address, Discovered,
+    DiscoveryStrategy,
+};
+use crate::store::StateStore;
+use crate::{telemetry, AppState};
+
+pub fn record_onion_address_discovery(started: Instant, nickname: &str, found: &Discovered) {
+    metrics::gauge!(telemetry::ONION_DISCOVERY.name, "service" => nickname.to_string())
+        .set(started.elapsed().as_secs_f64());
+    metrics::counter!(
+        telemetry::ONION_DISCOVERY_STRATEGY.name,
+        "service" => nickname.to_string(),
+        "strategy" => found.strategy,
+        "deprecated" => found.deprecated.to_string()
+    )
+    .increment(1);
+}
+
+/// Key of the last discovered address of the `nickname` service, kept in the state store so
+/// pages can show it right after a restart instead of once discovery has caught up.
+pub fn onion_address_cache_key(nickname: &str) -> String {
+    format!("onion-address.{nickname}")
+}
+
+/// Describes how the `nickname` service's identity in the keystore under `state_dir` departs
+/// from `expected`, the address earlier runs served, or returns `None` if it doesn't. A missing
+/// key counts: arti would generate a fresh one on launch.
+pub fn identity_change(state_dir: &Path, nickname: &str, expected: &str) -> Option<String> {
+    match read_onion_address(state_dir, nickname) {
+        Ok(found) if found == expected => None,
+        Ok(found) => Some(format!(
+            "onion service {nickname:?} has identity {found} in the keystore, but earlier runs served {expected}"
+        )),
+        Err(e) => Some(format!(
+            "onion service {nickname:?} has no usable identity key ({e}), so arti would replace {expected} with a new identity"
+        )),
+    }
+}
+
+/// Reads the onion address cached by an earlier run, ignoring anything that isn't one.
+pub async fn read_cached_onion_address(store: &dyn StateStore, nickname: &str) -> Option<String> {
+    let key = onion_address_cache_key(nickname);
+    let cached = match store.get(&key).await {
+        Ok(cached) => cached?,
+        Err(e) => {
+            warn!(cache = %store.location(&key), error = %e, "unable to read the cached onion address");
+            return None;
+        }
+    };
+    let cached = String::from_utf8(cached).ok()?;
+    let cached = cached.trim();
+    onion_public_key(cached).map(|_| cached.to_string())
+}
+
+/// Discovers the address of the `nickname` service and hands it to the handlers, replacing the
+/// cached address if it changed.
+async fn publish_onion_address(
+    strategies: Arc<[Box<dyn DiscoveryStrategy>]>,
+    state: Arc<AppState>,
+    nickname: String,
+    timeout: Duration,
+) {
+    let Some(found) =
+        discover_onion_address(&strategies, &nickname, state.arti_status.clone(), timeout).await
+    else {
+        return;
+    };
+    record_onion_address_discovery(state.started, &nickname, &found);
+    let found = found.onion_address;
+    if state
+        .pgp_proof
+        .as_deref()
+        .is_some_and(|proof| !proof.contains(&found))
+    {
+        warn!(
+            onion_address = %found,
+            "the PGP ownership statement does not mention the onion address; it may be stale"
+        );
+    }
+
+    let previous = state
+        .onion_addresses
+        .write()
+        .insert(nickname.clone(), found.clone());
+    if previous.as_ref() == Some(&found) {
+        return;
+    }
+    if let Some(previous) = previous {
+        warn!(
+            %previous,
+            onion_address = %found,
+            "onion address differs from the one cached by an earlier run"
+        );
+    }
+    let key = onion_address_cache_key(&nickname);
+    let cached = format!("{found}\n").into_bytes();
+    if let Err(e) = state.state_store.put(&key, cached).await {
+        let cache = state.state_store.location(&key);
+        warn!(%cache, error = %e, "unable to cache the onion address");
+    }
+}
+
+/// Fire-and-forget tasks to discover each onion address from arti.
+#[cfg_attr(feature = "embedded-arti", allow(dead_code))]
+pub fn discover_onion_addresses(state: &Arc<AppState>) {
+    let control = &state.arti_control;
+    let strategies: Arc<[_]> =
+        discovery_strategies(&control.arti, &state.processes, control.discovery_fallback).into();
+    for service in state.onion_services.iter() {
+        let nickname = service.nickname.clone();
+        tokio::spawn(
+            publish_onion_address(
+                strategies.clone(),
+                state.clone(),
+                nickname.clone(),
+                control.discovery_timeout,
+            )
+            .instrument(info_span!("discovery", service = %nickname)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::discovery::onion_address;
+
+    use super::*;
+
+    #[test]
+    fn identity_changes_are_told_apart_from_the_expected_key() {
+        use base64::Engine;
+
+        let state_dir = env::temp_dir().join(format!("state-{}", rand::random::<u32>()));
+        let write_key = |key: [u8; 32]| {
+            let mut blob = Vec::new();
+            for field in [&b"ssh-ed25519"[..], &key] {
+                blob.extend_from_slice(&(field.len() as u32).to_be_bytes());
+                blob.extend_from_slice(field);
+            }
+            let encoded = base64::engine::general_purpose::STANDARD.encode(blob);
+            let dir = state_dir.join("keystore").join("hss").join("demo");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                dir.join("ks_hs_id.ed25519_public"),
+                format!("ssh-ed25519 {encoded}\n"),
+            )
+            .unwrap();
+        };
+        let expected = onion_address(&[1; 32]);
+
+        let missing = identity_change(&state_dir, "demo", &expected).unwrap();
+        assert!(missing.contains("no usable identity key"), "{missing}");
+        write_key([1; 32]);
+        assert_eq!(identity_change(&state_dir, "demo", &expected), None);
+        write_key([2; 32]);
+        let replaced = identity_change(&state_dir, "demo", &expected).unwrap();
+        assert!(replaced.contains(&onion_address(&[2; 32])), "{replaced}");
+
+        std::fs::remove_dir_all(&state_dir).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+//! The admin endpoints' access control, and the ones for arti restarts, the log buffer and its
+//! stream, and the mirrored requests.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        FromRequestParts, State,
+    },
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tracing::{warn, Instrument};
+
+use crate::addresses::discover_onion_addresses;
+use crate::signals::ShutdownSignal;
+use crate::supervisor::ArtiStatus;
+use crate::{AppState, LogBuffer, LogFilter, LogLine, LogSource, LOG_BUFFER};
+
+/// Compares in constant time, so a token can't be guessed byte by byte from response times.
+pub fn tokens_match(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[derive(Debug, Deserialize)]
+struct AdminQuery {
+    /// Alternative to the `Authorization` header, for browsers opening the WebSocket tail
+    token: Option<String>,
+}
+
+/// Checks the bearer token, or the `?token=` query when `allow_query_token` is set.
+///
+/// Only the WebSocket tail takes the query, since browsers can't set headers on a WebSocket;
+/// anywhere else the token would end up in access logs and browser history for nothing.
+async fn authorize_admin(
+    parts: &Parts,
+    state: &AppState,
+    allow_query_token: bool,
+) -> Result<(), Response> {
+    let query = if allow_query_token {
+        axum::extract::Query::<AdminQuery>::try_from_uri(&parts.uri)
+            .map_err(IntoResponse::into_response)?
+            .0
+    } else {
+        AdminQuery { token: None }
+    };
+    let given = parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query.token.as_deref());
+    let authorized = match (given, &state.admin_token) {
+        (Some(given), Some(expected)) => tokens_match(given.as_bytes(), expected.as_bytes()),
+        _ => false,
+    };
+    if !authorized {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response());
+    }
+    Ok(())
+}
+
+/// A request from a holder of `--admin-token`, sent in the `Authorization` header.
+pub struct AdminAccess;
+
+impl FromRequestParts<Arc<AppState>> for AdminAccess {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        authorize_admin(parts, state, false).await?;
+        Ok(Self)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LogsQuery {
+    /// Least severe level to include (`trace` through `error`); everything buffered by default
+    level: Option<String>,
+    /// `wrapper` or `arti`; both by default
+    source: Option<LogSource>,
+}
+
+/// A request to `/admin/logs` from a holder of `--admin-token`, with the lines it asked for.
+pub struct LogAccess {
+    buffer: &'static LogBuffer,
+    filter: LogFilter,
+}
+
+impl LogAccess {
+    async fn extract(
+        parts: &Parts,
+        state: &AppState,
+        allow_query_token: bool,
+    ) -> Result<Self, Response> {
+        authorize_admin(parts, state, allow_query_token).await?;
+        let axum::extract::Query(query) =
+            axum::extract::Query::<LogsQuery>::try_from_uri(&parts.uri)
+                .map_err(IntoResponse::into_response)?;
+        let level = match query.level.as_deref().map(str::parse::<tracing::Level>) {
+            None => tracing::Level::TRACE,
+            Some(Ok(level)) => level,
+            Some(Err(_)) => {
+                return Err((StatusCode::BAD_REQUEST, "unknown log level\n").into_response());
+            }
+        };
+        let buffer = LOG_BUFFER.get().ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                "log buffering is disabled (--log-buffer-kb 0)\n",
+            )
+                .into_response()
+        })?;
+        Ok(Self {
+            buffer,
+            filter: LogFilter {
+                level,
+                source: query.source,
+            },
+        })
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for LogAccess {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        Self::extract(parts, state, false).await
+    }
+}
+
+/// A [`LogAccess`] that may also carry the token as `?token=`, for the WebSocket tail.
+pub struct LogStreamAccess(LogAccess);
+
+impl FromRequestParts<Arc<AppState>> for LogStreamAccess {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        LogAccess::extract(parts, state, true).await.map(Self)
+    }
+}
+
+/// Serves the buffered log lines.
+pub async fn admin_logs_handler(access: LogAccess) -> Response {
+    access.buffer.read(access.filter).into_response()
+}
+
+/// Serves the mirrored onion requests.
+pub async fn admin_mirror_handler(_: AdminAccess, State(state): State<Arc<AppState>>) -> Response {
+    match &state.mirror {
+        Some(mirror) => Json(mirror.requests()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Kills arti and launches it again, then looks up the onion addresses afresh.
+pub async fn admin_arti_restart_handler(
+    _: AdminAccess,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    match restart_arti(&state, "arti restart requested through the admin API") {
+        Ok(()) => (StatusCode::ACCEPTED, "restarting arti\n").into_response(),
+        Err(refusal) => (StatusCode::CONFLICT, refusal).into_response(),
+    }
+}
+
+/// Has the supervisor relaunch arti and rediscovers the onion addresses once it has, or
+/// explains why arti can't be relaunched now.
+pub fn restart_arti(state: &Arc<AppState>, reason: &str) -> Result<(), &'static str> {
+    let Some(requests) = &state.arti_control.requests else {
+        return Err("arti runs in the foreground; restart the service instead\n");
+    };
+    match *state.arti_status.borrow() {
+        ArtiStatus::Running | ArtiStatus::Backoff { .. } => {}
+        ArtiStatus::Starting => return Err("arti is still starting\n"),
+        ArtiStatus::Exhausted => {
+            return Err("arti is no longer relaunched after repeated failures\n");
+        }
+    }
+    warn!("{reason}");
+    let mut status = state.arti_status.clone();
+    status.mark_unchanged();
+    requests.request();
+    if !cfg!(feature = "embedded-arti") {
+        let state = state.clone();
+        tokio::spawn(
+            async move {
+                // Discovery waits for arti to be running, which the old process still is until
+                // the supervisor reports it gone
+                if status.changed().await.is_ok() {
+                    discover_onion_addresses(&state);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    Ok(())
+}
+
+/// Upgrades to a WebSocket that receives each new log line as a text message.
+pub async fn admin_logs_stream_handler(
+    State(state): State<Arc<AppState>>,
+    LogStreamAccess(access): LogStreamAccess,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let lines = access.buffer.subscribe();
+    let shutdown = state.shutdown.subscribe();
+    upgrade.on_upgrade(move |socket| tail_logs(socket, lines, access.filter, shutdown))
+}
+
+/// Sends the lines admitted by `filter` until the client goes away or shutdown is requested.
+///
+/// A client too slow to keep up is told how many lines it missed rather than disconnected.
+async fn tail_logs(
+    mut socket: WebSocket,
+    mut lines: broadcast::Receiver<Arc<LogLine>>,
+    filter: LogFilter,
+    mut shutdown: ShutdownSignal,
+) {
+    loop {
+        let message = tokio::select! {
+            line = lines.recv() => match line {
+                Ok(line) if filter.admits(&line) => Message::text(line.text.as_str()),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    Message::text(format!("({skipped} lines skipped)\n"))
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(_)) => continue,
+                _ => return,
+            },
+            _ = shutdown.recv() => break,
+        };
+        if socket.send(message).await.is_err() {
+            return;
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
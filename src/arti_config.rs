@@ -0,0 +1,294 @@
+//! arti's configuration file: reading the paths and ports the server needs from it, and
+//! rendering one from the command line for `--generate-arti-config`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::arti_logging::ArtiLogConfig;
+use crate::client_auth::{client_auth_dir, CLIENT_AUTH_DIR};
+use crate::config::CliArgs;
+use crate::{parse_onion_services, Error, OnionService};
+
+/// The parts of an arti configuration file the wrapper needs to know about.
+#[derive(Debug, Default, Deserialize)]
+pub struct ArtiConfigFile {
+    #[serde(default)]
+    pub storage: ArtiStorageConfig,
+    /// Onion service sections by nickname; only their presence is checked
+    #[serde(default)]
+    #[cfg_attr(feature = "embedded-arti", allow(dead_code))]
+    pub onion_services: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    #[cfg_attr(feature = "embedded-arti", allow(dead_code))]
+    pub proxy: ArtiProxyConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ArtiProxyConfig {
+    /// A port on localhost, an address, a list of either, or 0 to disable the listener
+    #[cfg_attr(feature = "embedded-arti", allow(dead_code))]
+    pub socks_listen: Option<toml::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ArtiStorageConfig {
+    pub state_dir: Option<String>,
+    #[cfg_attr(not(feature = "embedded-arti"), allow(dead_code))]
+    cache_dir: Option<String>,
+}
+
+/// Expands the path variables arti supports in its configuration (`${ARTI_LOCAL_DATA}`, `~`, ...).
+pub fn expand_arti_path(path: &str) -> Result<PathBuf, Error> {
+    let home = || {
+        env::var("HOME")
+            .map_err(|_| Error::Command(format!("cannot expand {path}: HOME is not set")))
+    };
+
+    let mut expanded = path.to_string();
+    for (variable, suffix) in [
+        ("${ARTI_LOCAL_DATA}", ".local/share/arti"),
+        ("${ARTI_CACHE}", ".cache/arti"),
+        ("${ARTI_CONFIG}", ".config/arti"),
+        ("${USER_HOME}", ""),
+    ] {
+        if expanded.contains(variable) {
+            let value = PathBuf::from(home()?).join(suffix);
+            expanded = expanded.replace(variable, &value.to_string_lossy());
+        }
+    }
+    if let Some(rest) = expanded.strip_prefix("~/") {
+        expanded = PathBuf::from(home()?)
+            .join(rest)
+            .to_string_lossy()
+            .into_owned();
+    }
+    if expanded.contains("${") {
+        return Err(Error::Command(format!(
+            "unsupported variable in arti path {path}"
+        )));
+    }
+    Ok(PathBuf::from(expanded))
+}
+
+/// Reads arti's state directory from its configuration file, falling back to arti's default.
+pub fn arti_state_dir(config: &Path) -> Result<PathBuf, Error> {
+    expand_arti_path(
+        read_arti_config(config)?
+            .storage
+            .state_dir
+            .as_deref()
+            .unwrap_or("${ARTI_LOCAL_DATA}/state"),
+    )
+}
+
+/// Reads arti's cache directory from its configuration file, falling back to arti's default.
+#[cfg(feature = "embedded-arti")]
+pub fn arti_cache_dir(config: &Path) -> Result<PathBuf, Error> {
+    expand_arti_path(
+        read_arti_config(config)?
+            .storage
+            .cache_dir
+            .as_deref()
+            .unwrap_or("${ARTI_CACHE}"),
+    )
+}
+
+/// Arti's SOCKS port when none is configured.
+const ARTI_DEFAULT_SOCKS_PORT: u16 = 9150;
+
+/// The first address arti's SOCKS proxy listens on according to the configuration at `path`,
+/// or `None` if it is disabled.
+#[cfg_attr(feature = "embedded-arti", allow(dead_code))]
+pub fn arti_socks_address(
+    path: &Path,
+    config: &ArtiConfigFile,
+) -> Result<Option<SocketAddr>, Error> {
+    let listen = match config.proxy.socks_listen.clone() {
+        Some(toml::Value::Array(listeners)) => listeners.into_iter().next(),
+        listen => listen,
+    };
+    let invalid = |value: &dyn std::fmt::Display| {
+        Error::Command(format!(
+            "{}: unsupported proxy.socks_listen value {value}",
+            path.display()
+        ))
+    };
+    match listen {
+        None => Ok(Some(SocketAddr::from((
+            Ipv4Addr::LOCALHOST,
+            ARTI_DEFAULT_SOCKS_PORT,
+        )))),
+        Some(toml::Value::Integer(0)) => Ok(None),
+        Some(toml::Value::Integer(port)) => u16::try_from(port)
+            .map(|port| Some(SocketAddr::from((Ipv4Addr::LOCALHOST, port))))
+            .map_err(|_| invalid(&port)),
+        Some(toml::Value::String(address)) => {
+            address.parse().map(Some).map_err(|_| invalid(&address))
+        }
+        Some(other) => Err(invalid(&other)),
+    }
+}
+
+pub fn read_arti_config(config: &Path) -> Result<ArtiConfigFile, Error> {
+    let contents = std::fs::read_to_string(config)
+        .map_err(|e| Error::Command(format!("Unable to read {}: {e:?}", config.display())))?;
+    parse_arti_config(config, &contents)
+}
+
+pub fn parse_arti_config(config: &Path, contents: &str) -> Result<ArtiConfigFile, Error> {
+    toml::from_str(contents)
+        .map_err(|e| Error::Command(format!("Unable to parse {}: {e}", config.display())))
+}
+
+/// First line of the generated arti configuration.
+pub const GENERATED_ARTI_CONFIG_HEADER: &str =
+    "# Generated by arti-axum-railway --generate-arti-config and rewritten on every start\n";
+
+/// arti configuration shipped with the Docker image, used as the template for `init`.
+pub const ONIONSERVICE_TEMPLATE: &str = include_str!("../onionservice.toml");
+
+/// Renders the arti configuration for `--generate-arti-config`: the bundled template, with one
+/// section per onion service (modelled on the template's `demo`) and the storage, SOCKS and
+/// logging settings taken from the options.
+pub fn render_arti_config(args: &CliArgs, services: &[OnionService]) -> String {
+    let mut config: toml::Table = ONIONSERVICE_TEMPLATE
+        .parse()
+        .expect("the bundled arti configuration parses");
+    let section = |config: &mut toml::Table, name: &str| -> toml::Table {
+        match config.remove(name) {
+            Some(toml::Value::Table(table)) => table,
+            _ => panic!("the bundled arti configuration has a [{name}] table"),
+        }
+    };
+
+    let mut template = section(&mut config, "onion_services");
+    let template = section(&mut template, "demo");
+    let onion_services: toml::Table = services
+        .iter()
+        .map(|service| {
+            let mut settings = template.clone();
+            let target = format!("127.0.0.1:{}", service.port);
+            settings.insert(
+                "proxy_ports".to_string(),
+                toml::Value::Array(vec![toml::Value::Array(vec!["80".into(), target.into()])]),
+            );
+            if args.restricted_discovery {
+                // The directory client_auth_dir finds from the state directory
+                let key_dir = format!(
+                    "{}/{CLIENT_AUTH_DIR}/{}",
+                    args.arti_state_dir, service.nickname
+                );
+                let key_dir = toml::Table::from_iter([("path".to_string(), key_dir.into())]);
+                let restricted = toml::Table::from_iter([
+                    ("enabled".to_string(), true.into()),
+                    (
+                        "key_dirs".to_string(),
+                        toml::Value::Array(vec![key_dir.into()]),
+                    ),
+                ]);
+                settings.insert("restricted_discovery".to_string(), restricted.into());
+            }
+            (service.nickname.clone(), settings.into())
+        })
+        .collect();
+    if !onion_services.is_empty() {
+        config.insert("onion_services".to_string(), onion_services.into());
+    }
+
+    config.insert(
+        "logging".to_string(),
+        ArtiLogConfig::from_args(args).to_toml().into(),
+    );
+    let mut proxy = section(&mut config, "proxy");
+    proxy.insert(
+        "socks_listen".to_string(),
+        i64::from(args.arti_socks_port).into(),
+    );
+    config.insert("proxy".to_string(), proxy.into());
+    let mut storage = section(&mut config, "storage");
+    storage.insert("state_dir".to_string(), args.arti_state_dir.clone().into());
+    storage.insert("cache_dir".to_string(), args.arti_cache_dir.clone().into());
+    config.insert("storage".to_string(), storage.into());
+
+    generated_arti_config(&config)
+}
+
+/// Serializes a generated arti configuration, header included.
+pub fn generated_arti_config(config: &toml::Table) -> String {
+    format!(
+        "{GENERATED_ARTI_CONFIG_HEADER}{}",
+        toml::to_string(config).expect("TOML tables serialize")
+    )
+}
+
+/// Writes `contents` aside and renames them over `path`, so arti never reads a half-written file.
+pub fn replace_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(&temp_path, path)
+}
+
+/// The services the generated arti configuration has a section for.
+pub fn generated_onion_services(args: &CliArgs) -> Result<Vec<OnionService>, Error> {
+    match args.client_only {
+        true => Ok(Vec::new()),
+        false => parse_onion_services(&args.onion_services, args.onion_port),
+    }
+}
+
+/// Writes the generated arti configuration to `--config`, replacing the previous one.
+pub fn write_arti_config(args: &CliArgs) -> Result<(), Error> {
+    let services = generated_onion_services(args)?;
+    let path = &args.config;
+    let failed =
+        |e: std::io::Error| Error::Startup(format!("Unable to write {}: {e:?}", path.display()));
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(failed)?;
+    }
+    replace_file(path, &render_arti_config(args, &services)).map_err(failed)?;
+    info!(config = %path.display(), "generated the arti configuration");
+    if args.restricted_discovery {
+        // arti won't start with a key directory missing, even before any client is authorized
+        let state_dir = args.state_dir()?;
+        for service in &services {
+            let dir = client_auth_dir(&state_dir, &service.nickname);
+            let mut builder = std::fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            builder.create(&dir).map_err(|e| {
+                Error::Startup(format!("Unable to create {}: {e:?}", dir.display()))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socks_address_follows_arti_proxy_config() {
+        let path = env::temp_dir().join(format!("arti-{}.toml", rand::random::<u32>()));
+        let socks = |config: &str| {
+            std::fs::write(&path, config).unwrap();
+            let config = read_arti_config(&path).unwrap();
+            arti_socks_address(&path, &config).map(|address| address.map(|a| a.to_string()))
+        };
+
+        assert_eq!(socks("").unwrap().as_deref(), Some("127.0.0.1:9150"));
+        let ported = socks("[proxy]\nsocks_listen = 9050\n").unwrap();
+        assert_eq!(ported.as_deref(), Some("127.0.0.1:9050"));
+        let listed = socks("[proxy]\nsocks_listen = [\"[::1]:9999\", 9050]\n").unwrap();
+        assert_eq!(listed.as_deref(), Some("[::1]:9999"));
+        assert_eq!(socks("[proxy]\nsocks_listen = 0\n").unwrap(), None);
+        assert!(socks("[proxy]\nsocks_listen = \"localhost\"\n").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+//! arti's own logging in the generated configuration: the console filter forwarded into this
+//! log, rotating log files, and pruning them to `--arti-log-max-mib`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::admin::{restart_arti, AdminAccess};
+use crate::arti_config::{expand_arti_path, generated_arti_config, replace_file};
+use crate::config::{parse_arti_log_filter, ArtiLogRotate, CliArgs};
+use crate::schedule::Schedule;
+use crate::signals::ShutdownSignal;
+use crate::{AppState, Error};
+
+/// Name of arti's log file in `--arti-log-dir`; rotated files carry the date after it.
+const ARTI_LOG_FILE: &str = "arti.log";
+
+/// How often `--arti-log-dir` is trimmed back under `--arti-log-max-mib`.
+const ARTI_LOG_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The `[logging]` section of the generated arti configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArtiLogConfig {
+    /// Filter for arti's console output, which is forwarded into this log
+    pub console: String,
+    pub files: Option<ArtiLogFiles>,
+}
+
+/// Rotating log files arti writes with `--arti-log-dir`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArtiLogFiles {
+    /// The directory as arti's configuration names it, variables included
+    dir: String,
+    pub filter: String,
+    pub rotate: ArtiLogRotate,
+}
+
+impl ArtiLogConfig {
+    pub fn from_args(args: &CliArgs) -> Self {
+        ArtiLogConfig {
+            console: args.arti_log_level.clone(),
+            files: args.arti_log_dir.as_ref().map(|dir| ArtiLogFiles {
+                dir: dir.trim_end_matches('/').to_string(),
+                filter: args.arti_log_file_level.clone(),
+                rotate: args.arti_log_rotate,
+            }),
+        }
+    }
+
+    pub fn to_toml(&self) -> toml::Table {
+        let mut logging = toml::Table::new();
+        logging.insert("console".to_string(), self.console.clone().into());
+        if let Some(files) = &self.files {
+            let file = toml::Table::from_iter([
+                (
+                    "path".to_string(),
+                    format!("{}/{ARTI_LOG_FILE}", files.dir).into(),
+                ),
+                ("filter".to_string(), files.filter.clone().into()),
+                ("rotate".to_string(), files.rotate.name().into()),
+            ]);
+            logging.insert("files".to_string(), toml::Value::Array(vec![file.into()]));
+        }
+        logging
+    }
+}
+
+/// Lets `/admin/arti/logging` change the log filters of the generated arti configuration.
+///
+/// Changes last until the configuration is generated again on the next start.
+pub struct ArtiLogging {
+    /// The generated configuration
+    config: PathBuf,
+    pub current: Mutex<ArtiLogConfig>,
+    /// `--arti-log-dir` resolved, for measuring and trimming it
+    pub dir: Option<PathBuf>,
+    /// `--arti-log-max-mib` in bytes; 0 for no cap
+    pub max_bytes: u64,
+    /// The configuration as last written, so the file watcher doesn't report our own rewrites
+    written: Mutex<String>,
+}
+
+impl ArtiLogging {
+    pub fn new(args: &CliArgs) -> Result<Self, Error> {
+        let logging = ArtiLogConfig::from_args(args);
+        let dir = match &logging.files {
+            Some(files) => Some(
+                args.base_dir()
+                    .join(expand_arti_path(&files.dir).map_err(|e| Error::Startup(e.to_string()))?),
+            ),
+            None => None,
+        };
+        Ok(ArtiLogging {
+            config: args.config.clone(),
+            current: Mutex::new(logging),
+            dir,
+            max_bytes: args.arti_log_max_mib.saturating_mul(1024 * 1024),
+            written: Mutex::new(std::fs::read_to_string(&args.config).unwrap_or_default()),
+        })
+    }
+
+    /// Whether `contents` are what was last written to the configuration.
+    pub fn wrote(&self, contents: &str) -> bool {
+        *self.written.lock() == contents
+    }
+
+    /// Replaces the log filters in the configuration, returning whether anything changed.
+    pub fn set_filters(
+        &self,
+        console: Option<String>,
+        file: Option<String>,
+    ) -> Result<bool, Error> {
+        let mut current = self.current.lock();
+        let mut logging = current.clone();
+        if let Some(console) = console {
+            logging.console = console;
+        }
+        if let Some(filter) = file {
+            let Some(files) = &mut logging.files else {
+                return Err(Error::Command(
+                    "arti doesn't log to files; start with --arti-log-dir".to_string(),
+                ));
+            };
+            files.filter = filter;
+        }
+        if logging == *current {
+            return Ok(false);
+        }
+        let failed =
+            |e: String| Error::Runtime(format!("Unable to rewrite {}: {e}", self.config.display()));
+        let mut config: toml::Table = std::fs::read_to_string(&self.config)
+            .map_err(|e| failed(format!("{e:?}")))?
+            .parse()
+            .map_err(|e: toml::de::Error| failed(e.to_string()))?;
+        config.insert("logging".to_string(), logging.to_toml().into());
+        let contents = generated_arti_config(&config);
+        let mut written = self.written.lock();
+        *written = contents.clone();
+        replace_file(&self.config, &contents).map_err(|e| failed(format!("{e:?}")))?;
+        *current = logging;
+        Ok(true)
+    }
+}
+
+/// The log files to delete to bring their total under `max_bytes`, oldest first; the newest is
+/// kept regardless, since arti is still writing it.
+fn arti_logs_to_prune(mut logs: Vec<(PathBuf, SystemTime, u64)>, max_bytes: u64) -> Vec<PathBuf> {
+    logs.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    let mut total: u64 = logs.iter().map(|(_, _, size)| size).sum();
+    logs.pop();
+    let mut prune = Vec::new();
+    for (path, _, size) in logs {
+        if total <= max_bytes {
+            break;
+        }
+        total -= size;
+        prune.push(path);
+    }
+    prune
+}
+
+/// arti's log files in `dir`, with when each was last written and its size.
+fn arti_log_files(dir: &Path) -> std::io::Result<Vec<(PathBuf, SystemTime, u64)>> {
+    let mut logs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(ARTI_LOG_FILE)
+        {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            logs.push((entry.path(), metadata.modified()?, metadata.len()));
+        }
+    }
+    Ok(logs)
+}
+
+/// Deletes arti's oldest log files whenever they grow past `--arti-log-max-mib`.
+pub async fn prune_arti_logs(logging: Arc<ArtiLogging>, mut shutdown: ShutdownSignal) {
+    let (Some(dir), 1..) = (logging.dir.clone(), logging.max_bytes) else {
+        return;
+    };
+    let mut schedule = Schedule::every(ARTI_LOG_PRUNE_INTERVAL).starting_now();
+    while schedule.tick(&mut shutdown).await {
+        let logs = match arti_log_files(&dir) {
+            Ok(logs) => logs,
+            // arti creates the directory when it first logs
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                warn!(dir = %dir.display(), "unable to list arti's log files: {e}");
+                continue;
+            }
+        };
+        for path in arti_logs_to_prune(logs, logging.max_bytes) {
+            match std::fs::remove_file(&path) {
+                Ok(()) => info!(file = %path.display(), "deleted an old arti log file"),
+                Err(e) => {
+                    warn!(file = %path.display(), "unable to delete an old arti log file: {e}")
+                }
+            }
+        }
+    }
+}
+
+/// Current log configuration of arti, and how much space its log files take up.
+pub async fn admin_arti_logging_handler(
+    _: AdminAccess,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let Some(logging) = &state.arti_logging else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let current = logging.current.lock().clone();
+    let used_bytes = logging.dir.as_deref().map(|dir| {
+        arti_log_files(dir)
+            .map(|logs| logs.iter().map(|(_, _, size)| size).sum::<u64>())
+            .unwrap_or(0)
+    });
+    Json(serde_json::json!({
+        "console": current.console,
+        "files": current.files.map(|files| serde_json::json!({
+            "dir": files.dir,
+            "filter": files.filter,
+            "rotate": files.rotate,
+            "max_bytes": logging.max_bytes,
+            "used_bytes": used_bytes,
+        })),
+    }))
+    .into_response()
+}
+
+/// Body of `PUT /admin/arti/logging`; filters left out are kept.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArtiLogFilterChange {
+    console: Option<String>,
+    file: Option<String>,
+}
+
+/// Changes arti's log filters in the generated configuration, then restarts arti.
+pub async fn admin_arti_logging_change_handler(
+    _: AdminAccess,
+    State(state): State<Arc<AppState>>,
+    body: axum::body::Bytes,
+) -> Response {
+    let Some(logging) = &state.arti_logging else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let change = match serde_json::from_slice::<ArtiLogFilterChange>(&body) {
+        Ok(change) => change,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{e}\n")).into_response(),
+    };
+    let validated = (change.console.as_deref().map(parse_arti_log_filter))
+        .transpose()
+        .and_then(|console| {
+            let file = change.file.as_deref().map(parse_arti_log_filter);
+            Ok((console, file.transpose()?))
+        });
+    let (console, file) = match validated {
+        Ok(filters) => filters,
+        Err(message) => return (StatusCode::BAD_REQUEST, format!("{message}\n")).into_response(),
+    };
+    match logging.set_filters(console, file) {
+        Ok(false) => (StatusCode::OK, "unchanged\n").into_response(),
+        Ok(true) => {
+            let current = logging.current.lock().clone();
+            let mut filters = format!("console {}", current.console);
+            if let Some(files) = &current.files {
+                filters.push_str(&format!(", files {}", files.filter));
+            }
+            let reason = format!("arti log filters changed to {filters}; restarting arti");
+            match restart_arti(&state, &reason) {
+                Ok(()) => (StatusCode::ACCEPTED, "changed; restarting arti\n").into_response(),
+                // Saved, and picked up whenever arti next starts
+                Err(_) => (
+                    StatusCode::OK,
+                    "changed; takes effect when arti next starts\n",
+                )
+                    .into_response(),
+            }
+        }
+        Err(Error::Command(message)) => {
+            (StatusCode::CONFLICT, format!("{message}\n")).into_response()
+        }
+        Err(e) => {
+            error!(error = %e, "unable to change arti's log filters");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    #[test]
+    fn oldest_arti_logs_are_pruned_down_to_the_cap() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let logs = vec![
+            (PathBuf::from("arti.log.2026-10-14"), at(2), 40),
+            (PathBuf::from("arti.log.2026-10-15"), at(3), 30),
+            (PathBuf::from("arti.log.2026-10-13"), at(1), 50),
+        ];
+        assert!(arti_logs_to_prune(logs.clone(), 120).is_empty());
+        assert_eq!(
+            arti_logs_to_prune(logs.clone(), 100),
+            [PathBuf::from("arti.log.2026-10-13")]
+        );
+        assert_eq!(
+            arti_logs_to_prune(logs.clone(), 50),
+            [
+                PathBuf::from("arti.log.2026-10-13"),
+                PathBuf::from("arti.log.2026-10-14")
+            ]
+        );
+        // The file arti is writing stays, even over the cap
+        assert_eq!(arti_logs_to_prune(logs, 10).len(), 2);
+    }
+}
@@ -0,0 +1,504 @@
+//! Restricted discovery: the clients allowed to reach an onion service, kept as key files in
+//! arti's state directory and managed with the `client-auth` subcommand or the
+//! `/admin/client-auth` endpoints.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use parking_lot::Mutex;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+use crate::admin::{restart_arti, AdminAccess};
+use crate::arti_config::arti_state_dir;
+use crate::config::ClientAuthAction;
+use crate::discovery::{base32_decode, base32_encode, Base32Case};
+use crate::{AppState, Error};
+
+/// The clients authorized for each onion service, by service nickname and client name.
+pub async fn admin_client_auth_handler(
+    _: AdminAccess,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let Some(root) = &state.client_auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut services = BTreeMap::new();
+    for service in state.onion_services.iter() {
+        match read_client_keys(&root.join(&service.nickname)) {
+            Ok(keys) => services.insert(service.nickname.clone(), keys),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n")).into_response(),
+        };
+    }
+    Json(services).into_response()
+}
+
+/// Authorizes a client with the x25519 public key in the body, or revokes it, then restarts
+/// arti so the next descriptor it publishes reflects the change.
+pub async fn admin_client_auth_change_handler(
+    _: AdminAccess,
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    axum::extract::Path((service, name)): axum::extract::Path<(String, String)>,
+    key: String,
+) -> Response {
+    let Some(root) = &state.client_auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !state.onion_services.iter().any(|s| s.nickname == service) {
+        return (StatusCode::NOT_FOUND, "no such onion service\n").into_response();
+    }
+    let valid = match method {
+        Method::DELETE => validate_client_name(&name),
+        _ => validate_client_name(&name).and_then(|()| parse_client_key(&key).map(drop)),
+    };
+    if let Err(Error::Command(message) | Error::Startup(message) | Error::Runtime(message)) = valid
+    {
+        return (StatusCode::BAD_REQUEST, format!("{message}\n")).into_response();
+    }
+    let dir = root.join(&service);
+    let (changed, verb) = match method {
+        Method::DELETE => (remove_client_key(&dir, &name), "revoked"),
+        _ => (write_client_key(&dir, &name, &key), "authorized"),
+    };
+    match changed {
+        Ok(false) if method == Method::DELETE => {
+            (StatusCode::NOT_FOUND, "no such client\n").into_response()
+        }
+        Ok(false) => (StatusCode::OK, "unchanged\n").into_response(),
+        Ok(true) => {
+            let reason =
+                format!("client {name} {verb} for onion service {service}; restarting arti");
+            match restart_arti(&state, &reason) {
+                Ok(()) => {
+                    (StatusCode::ACCEPTED, format!("{verb}; restarting arti\n")).into_response()
+                }
+                // Saved, and picked up whenever arti next starts
+                Err(_) => (
+                    StatusCode::OK,
+                    format!("{verb}; takes effect when arti next starts\n"),
+                )
+                    .into_response(),
+            }
+        }
+        Err(e) => {
+            error!(error = %e, "unable to change the authorized clients");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Generates a keypair for a client, authorizes its public key (replacing any key the client
+/// had) and holds the private credential for a single download.
+pub async fn admin_client_auth_generate_handler(
+    _: AdminAccess,
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path((service, name)): axum::extract::Path<(String, String)>,
+) -> Response {
+    let Some(root) = &state.client_auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !state.onion_services.iter().any(|s| s.nickname == service) {
+        return (StatusCode::NOT_FOUND, "no such onion service\n").into_response();
+    }
+    if let Err(Error::Command(message) | Error::Startup(message) | Error::Runtime(message)) =
+        validate_client_name(&name)
+    {
+        return (StatusCode::BAD_REQUEST, format!("{message}\n")).into_response();
+    }
+    // The credential names the service's address, so there is nothing to hand out before it
+    let Some(onion_address) = state.onion_addresses.read().get(&service).cloned() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "5")],
+            "the onion address is not known yet\n",
+        )
+            .into_response();
+    };
+    let (public_key, private_key) = client_keypair(rand::random());
+    if let Err(e) = write_client_key(&root.join(&service), &name, &public_key) {
+        error!(error = %e, "unable to change the authorized clients");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    let expires = Instant::now() + CLIENT_CREDENTIAL_TTL;
+    let token = state.client_credentials.insert(
+        PendingCredential {
+            service: service.clone(),
+            client: name.clone(),
+            public_key: public_key.clone(),
+            file: client_credential(&onion_address, &private_key),
+            expires,
+        },
+        Instant::now(),
+    );
+    let reason = format!("client {name} authorized for onion service {service}; restarting arti");
+    let restarting = restart_arti(&state, &reason).is_ok();
+    (
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "service": service,
+            "client": name,
+            "public_key": public_key,
+            "download": format!("/admin/client-auth/credentials/{token}"),
+            "expires_in_seconds": CLIENT_CREDENTIAL_TTL.as_secs(),
+            "restarting_arti": restarting,
+        })),
+    )
+        .into_response()
+}
+
+/// Serves a generated credential once, as long as its public key is still the one authorized
+/// for the client; it is forgotten either way.
+pub async fn admin_client_credential_handler(
+    _: AdminAccess,
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(token): axum::extract::Path<String>,
+) -> Response {
+    let Some(root) = &state.client_auth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(credential) = state.client_credentials.take(&token, Instant::now()) else {
+        return (
+            StatusCode::NOT_FOUND,
+            "no such credential; it may have been downloaded or expired\n",
+        )
+            .into_response();
+    };
+    match read_client_keys(&root.join(&credential.service)) {
+        Ok(keys) if keys.get(&credential.client) == Some(&credential.public_key) => {}
+        Ok(_) => {
+            return (
+                StatusCode::GONE,
+                "the client was revoked or given another key since this credential was generated\n",
+            )
+                .into_response();
+        }
+        Err(e) => {
+            error!(error = %e, "unable to read the authorized clients");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    info!(
+        service = credential.service,
+        client = credential.client,
+        "client credential downloaded"
+    );
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}.auth_private\"",
+                    credential.client
+                ),
+            ),
+        ],
+        credential.file,
+    )
+        .into_response()
+}
+
+/// Directory under arti's state directory holding each service's authorized clients, one
+/// `<client>.auth` file per client in a directory per service, as read by arti's
+/// `restricted_discovery.key_dirs`.
+pub const CLIENT_AUTH_DIR: &str = "restricted-discovery";
+
+pub fn client_auth_dir(state_dir: &Path, nickname: &str) -> PathBuf {
+    state_dir.join(CLIENT_AUTH_DIR).join(nickname)
+}
+
+/// Checks a client's name, which becomes its file name in the key directory.
+fn validate_client_name(name: &str) -> Result<(), Error> {
+    let valid = (1..=64).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    match valid {
+        true => Ok(()),
+        false => Err(Error::Command(format!(
+            "Invalid client name {name:?}: use 1 to 64 letters, digits, '-' or '_'"
+        ))),
+    }
+}
+
+/// Normalizes a client's x25519 public key to the `descriptor:x25519:<base32>` form arti reads,
+/// accepting the bare base32 key too (as printed by `arti hsc key get` or `tor-keygen`).
+fn parse_client_key(key: &str) -> Result<String, Error> {
+    let key = key.trim();
+    let encoded = key.strip_prefix("descriptor:x25519:").unwrap_or(key);
+    let encoded = encoded.to_ascii_uppercase();
+    // 32 bytes take 52 base32 characters, the last 4 bits of which are padding
+    match base32_decode(&encoded, Base32Case::Upper) {
+        Some(decoded) if decoded.len() == 32 => Ok(format!("descriptor:x25519:{encoded}")),
+        _ => Err(Error::Command(format!(
+            "Invalid client key {key:?}: expected an x25519 public key as descriptor:x25519:<base32>"
+        ))),
+    }
+}
+
+/// Reads the clients authorized in `dir`, by name; a missing directory has none.
+pub fn read_client_keys(dir: &Path) -> Result<BTreeMap<String, String>, Error> {
+    let failed =
+        |e: std::io::Error| Error::Command(format!("Unable to read {}: {e}", dir.display()));
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(failed(e)),
+    };
+    let mut keys = BTreeMap::new();
+    for entry in entries {
+        let path = entry.map_err(failed)?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".auth"))
+        else {
+            continue;
+        };
+        let key = std::fs::read_to_string(&path).map_err(failed)?;
+        keys.insert(name.to_string(), key.trim().to_string());
+    }
+    Ok(keys)
+}
+
+/// Authorizes `key` as client `name` in `dir`, replacing any key the client had; returns
+/// whether anything changed.
+fn write_client_key(dir: &Path, name: &str, key: &str) -> Result<bool, Error> {
+    validate_client_name(name)?;
+    let key = parse_client_key(key)?;
+    if read_client_keys(dir)?.get(name) == Some(&key) {
+        return Ok(false);
+    }
+    let failed =
+        |e: std::io::Error| Error::Command(format!("Unable to write to {}: {e}", dir.display()));
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    // arti refuses key directories others can write to
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir).map_err(failed)?;
+    // Written aside and renamed, so arti never reads a half-written key
+    let path = dir.join(format!("{name}.auth"));
+    let temp_path = dir.join(format!(".{name}.auth.tmp"));
+    std::fs::write(&temp_path, format!("{key}\n")).map_err(failed)?;
+    std::fs::rename(&temp_path, &path).map_err(failed)?;
+    Ok(true)
+}
+
+/// Revokes client `name`'s authorization in `dir`; returns whether it had one.
+fn remove_client_key(dir: &Path, name: &str) -> Result<bool, Error> {
+    validate_client_name(name)?;
+    match std::fs::remove_file(dir.join(format!("{name}.auth"))) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(Error::Command(format!(
+            "Unable to remove {name} from {}: {e}",
+            dir.display()
+        ))),
+    }
+}
+
+/// Derives a client's keypair from an x25519 secret: the public key in the
+/// `descriptor:x25519:<base32>` form arti reads, and the base32 private key.
+fn client_keypair(secret: [u8; 32]) -> (String, String) {
+    let public = curve25519_dalek::MontgomeryPoint::mul_base_clamped(secret).to_bytes();
+    // Clamped as x25519 uses it, so clients that don't clamp again derive the same key
+    let mut private = secret;
+    private[0] &= 248;
+    private[31] &= 127;
+    private[31] |= 64;
+    (
+        format!(
+            "descriptor:x25519:{}",
+            base32_encode(&public, Base32Case::Upper)
+        ),
+        base32_encode(&private, Base32Case::Upper),
+    )
+}
+
+/// The private half of a client's keypair for `onion_address`, as the `.auth_private` file Tor
+/// Browser and C Tor's `ClientOnionAuthDir` read; arti clients import it with
+/// `arti hsc ctor-migrate`.
+fn client_credential(onion_address: &str, private_key: &str) -> String {
+    let host = onion_address
+        .strip_suffix(".onion")
+        .unwrap_or(onion_address);
+    format!("{host}:descriptor:x25519:{private_key}\n")
+}
+
+/// How long a generated credential waits to be downloaded before it is dropped.
+const CLIENT_CREDENTIAL_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A generated client credential that hasn't been downloaded yet.
+#[derive(Debug, Clone)]
+struct PendingCredential {
+    service: String,
+    client: String,
+    /// The public key authorized for the client when the credential was generated
+    public_key: String,
+    file: String,
+    expires: Instant,
+}
+
+/// Generated credentials, each downloadable once by its token; the wrapper forgets the private
+/// key once it is downloaded or expires.
+#[derive(Debug, Default)]
+pub struct PendingCredentials {
+    credentials: Mutex<HashMap<String, PendingCredential>>,
+}
+
+impl PendingCredentials {
+    /// Holds `credential` for download, returning the token it can be fetched with once.
+    fn insert(&self, credential: PendingCredential, now: Instant) -> String {
+        let token = format!("{:032x}", rand::random::<u128>());
+        let mut credentials = self.credentials.lock();
+        credentials.retain(|_, pending| pending.expires > now);
+        credentials.insert(token.clone(), credential);
+        token
+    }
+
+    /// Hands out the credential behind `token`, forgetting it, unless it has expired.
+    fn take(&self, token: &str, now: Instant) -> Option<PendingCredential> {
+        let credential = self.credentials.lock().remove(token)?;
+        (credential.expires > now).then_some(credential)
+    }
+}
+
+/// Runs the `client-auth` subcommand against the key directory of the `nickname` service.
+pub fn client_auth(config: &Path, nickname: &str, action: ClientAuthAction) -> Result<(), Error> {
+    let dir = client_auth_dir(&arti_state_dir(config)?, nickname);
+    let changed = match action {
+        ClientAuthAction::List => {
+            for (name, key) in read_client_keys(&dir)? {
+                println!("{name} {key}");
+            }
+            return Ok(());
+        }
+        ClientAuthAction::Add { name, key } => write_client_key(&dir, &name, &key)?,
+        ClientAuthAction::Remove { name } => {
+            let removed = remove_client_key(&dir, &name)?;
+            if !removed {
+                return Err(Error::Command(format!(
+                    "{name} is not an authorized client"
+                )));
+            }
+            removed
+        }
+    };
+    if changed {
+        println!(
+            "updated {}; restart arti (POST /admin/arti/restart) for it to take effect",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn client_keys_are_validated_and_kept_one_file_per_client() {
+        let key = "PU63REQUH4PP464E2Y7AVQ35HBB5DXDH5XEUVUNP3KCPNOXZGIBA";
+        let descriptor = format!("descriptor:x25519:{key}");
+        assert_eq!(parse_client_key(key).unwrap(), descriptor);
+        assert_eq!(
+            parse_client_key(&format!(" {} \n", descriptor.to_lowercase())).unwrap(),
+            descriptor
+        );
+        // Too short, not base32, and nonzero padding bits
+        assert!(parse_client_key(&key[1..]).is_err());
+        assert!(parse_client_key(&key.replace('P', "1")).is_err());
+        assert!(parse_client_key(&format!("{}B", &key[..51])).is_err());
+        assert!(validate_client_name("alice_2").is_ok());
+        assert!(validate_client_name("../alice").is_err());
+        assert!(validate_client_name("").is_err());
+
+        let dir = env::temp_dir()
+            .join(format!("state-{}", rand::random::<u32>()))
+            .join("demo");
+        assert!(read_client_keys(&dir).unwrap().is_empty());
+        assert!(write_client_key(&dir, "alice", key).unwrap());
+        assert!(!write_client_key(&dir, "alice", &descriptor).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("alice.auth")).unwrap(),
+            format!("{descriptor}\n")
+        );
+        assert_eq!(
+            read_client_keys(&dir).unwrap(),
+            BTreeMap::from([("alice".to_string(), descriptor)])
+        );
+        assert!(remove_client_key(&dir, "alice").unwrap());
+        assert!(!remove_client_key(&dir, "alice").unwrap());
+        assert!(read_client_keys(&dir).unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn generated_client_credentials_are_downloadable_once() {
+        // RFC 7748's first Diffie-Hellman test vector
+        let secret: [u8; 32] = (0..32)
+            .map(|i| {
+                let hex = "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a";
+                u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap()
+            })
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+        let (public_key, private_key) = client_keypair(secret);
+        assert_eq!(
+            public_key,
+            "descriptor:x25519:QUQPACMJGCTVI5ELPXOLIPXXLIG36OQNEY4BV5HLUSUY5KU3JZVA"
+        );
+        assert_eq!(parse_client_key(&public_key).unwrap(), public_key);
+        assert_eq!(
+            private_key,
+            "OADW2CTTDCSX2PAWYFZFDMTGIXPUYL4H5PAJSKVRO752KHNZFRVA"
+        );
+        assert_eq!(
+            client_credential("abc.onion", &private_key),
+            format!("abc:descriptor:x25519:{private_key}\n")
+        );
+
+        let now = Instant::now();
+        let pending = |expires| PendingCredential {
+            service: "demo".to_string(),
+            client: "alice".to_string(),
+            public_key: public_key.clone(),
+            file: "credential".to_string(),
+            expires,
+        };
+        let credentials = PendingCredentials::default();
+        let token = credentials.insert(pending(now + CLIENT_CREDENTIAL_TTL), now);
+        assert!(credentials.take("unknown", now).is_none());
+        assert_eq!(credentials.take(&token, now).unwrap().file, "credential");
+        assert!(credentials.take(&token, now).is_none());
+
+        let token = credentials.insert(pending(now + CLIENT_CREDENTIAL_TTL), now);
+        assert!(credentials
+            .take(&token, now + CLIENT_CREDENTIAL_TTL)
+            .is_none());
+        // Expired credentials are dropped when the next one is generated
+        credentials.insert(pending(now + Duration::from_secs(1)), now);
+        credentials.insert(
+            pending(now + CLIENT_CREDENTIAL_TTL),
+            now + Duration::from_secs(2),
+        );
+        assert_eq!(credentials.credentials.lock().len(), 1);
+    }
+}
@@ -0,0 +1,1039 @@
+//! The command line and `--config-file`: every option the server takes, how a TOML file's values
+//! become their defaults, and the `config-schema` description of them.
+
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use axum::http::{HeaderValue, Method};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use crate::arti_config::{arti_state_dir, expand_arti_path};
+use crate::health::HealthCheck;
+use crate::{Error, DEFAULT_CONTENT_SECURITY_POLICY};
+
+/// Starts an Axum server, proxying connections from the Tor network as an Onion service.
+#[derive(Debug, Parser)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+    /// Server options, used when no subcommand is given
+    #[command(flatten)]
+    pub serve: Option<CliArgs>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Run the server (the default when no subcommand is given)
+    Serve(CliArgs),
+    /// Print the onion address from arti's keystore and exit
+    OnionAddress {
+        /// Path to the arti binary (optional, searches for an 'arti' binary in the current directory and PATH)
+        #[arg(short, long, env = "ARTI_BIN")]
+        arti: Option<PathBuf>,
+        /// Path to the arti configuration file
+        #[arg(short, long, env = "ARTI_CONFIG")]
+        config: PathBuf,
+        /// Nickname of the onion service in the arti configuration
+        #[arg(long, default_value = "demo")]
+        nickname: String,
+    },
+    /// Validate the server options and arti configuration without binding any ports
+    CheckConfig(CliArgs),
+    /// Print the version and enabled features
+    Version,
+    /// Print every configuration option with its type, default, and description
+    ConfigSchema {
+        #[arg(long, value_enum, default_value_t = SchemaFormat::Json)]
+        format: SchemaFormat,
+    },
+    /// Import a C Tor onion service's identity key into arti's keystore
+    ImportCtorKeys {
+        /// C Tor `HiddenServiceDir` containing `hs_ed25519_secret_key` and `hostname`
+        hidden_service_dir: PathBuf,
+        /// Path to the arti configuration file, used to locate the keystore
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Nickname of the onion service in the arti configuration
+        #[arg(long, default_value = "demo")]
+        nickname: String,
+        /// Replace an existing identity key for this nickname
+        #[arg(long)]
+        force: bool,
+    },
+    /// List, authorize, or revoke the clients of an onion service with restricted discovery
+    ClientAuth {
+        /// Path to the arti configuration file, used to locate the state directory
+        #[arg(short, long, env = "ARTI_CONFIG")]
+        config: PathBuf,
+        /// Nickname of the onion service in the arti configuration
+        #[arg(long, default_value = "demo")]
+        nickname: String,
+        #[command(subcommand)]
+        action: ClientAuthAction,
+    },
+    /// Back up or restore the onion services' keys, encrypted with a passphrase
+    Keys {
+        /// Path to the arti configuration file, used to locate the state directory
+        #[arg(short, long, env = "ARTI_CONFIG")]
+        config: PathBuf,
+        /// Passphrase the backup is encrypted with; prefer the environment variable or --passphrase-file, which other users can't see
+        #[arg(long, env = "KEYS_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+        /// File holding the passphrase, e.g. a mounted secret
+        #[arg(long, conflicts_with = "passphrase")]
+        passphrase_file: Option<PathBuf>,
+        #[command(subcommand)]
+        action: KeysAction,
+    },
+    /// Check a running deployment from the outside and print a pass/fail report, exiting with 1 if any check failed
+    SmokeTest {
+        /// Public URL of the deployment, e.g. `https://example.up.railway.app`
+        base_url: String,
+        /// SOCKS port of a local Tor or arti client to fetch the onion service through; that check is skipped when nothing listens there
+        #[arg(long, env = "SMOKE_TEST_SOCKS", default_value = "127.0.0.1:9150")]
+        socks: SocketAddr,
+        /// Seconds each request may take; the onion request gets four times as long to build its circuits
+        #[arg(long, default_value = "15")]
+        timeout_secs: u64,
+    },
+    /// Scaffold a standalone deployment: arti configuration, state directories, and a launcher
+    Init {
+        /// Directory to create the deployment in
+        target: PathBuf,
+        /// Port the onion service forwards to
+        #[arg(short, long, default_value = "3000")]
+        onion_port: u16,
+        /// Overwrite files left by a previous `init`
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Options for running the server.
+#[derive(Debug, Args)]
+pub struct CliArgs {
+    /// TOML file of server options keyed by their long name (e.g. `public-port = 8080`), overridden by environment variables and flags
+    #[arg(long, env = "CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+    /// Path to the arti binary (optional, searches for an 'arti' binary in the base directory and PATH)
+    #[arg(short, long, env = "ARTI_BIN")]
+    pub arti: Option<PathBuf>,
+    /// Path to the arti configuration file
+    #[arg(short, long, env = "ARTI_CONFIG")]
+    pub config: PathBuf,
+    /// Write the arti configuration to --config at startup, rendered from --onion-services, --arti-state-dir, --arti-cache-dir and --arti-socks-port, instead of reading a pre-made one
+    #[arg(long, env = "GENERATE_ARTI_CONFIG")]
+    pub generate_arti_config: bool,
+    /// arti's state directory, keystore included, in the generated configuration
+    #[arg(
+        long,
+        env = "ARTI_STATE_DIR",
+        default_value = "${ARTI_LOCAL_DATA}/state"
+    )]
+    pub arti_state_dir: String,
+    /// arti's cache directory in the generated configuration
+    #[arg(long, env = "ARTI_CACHE_DIR", default_value = "${ARTI_CACHE}")]
+    pub arti_cache_dir: String,
+    /// Publish every onion service's descriptor for authorized clients only, managed with the client-auth subcommand or the /admin/client-auth endpoints
+    #[arg(long, env = "RESTRICTED_DISCOVERY", requires = "generate_arti_config")]
+    pub restricted_discovery: bool,
+    /// Port arti's SOCKS proxy listens on in the generated configuration (0 disables it)
+    #[arg(long, env = "ARTI_SOCKS_PORT", default_value = "0")]
+    pub arti_socks_port: u16,
+    /// Filter for arti's console output in the generated configuration, which is forwarded into this log (e.g. `info` or `warn,tor_hsservice=debug`); bootstrap progress and descriptor uploads are only tracked at `info` or more verbose. Changeable at runtime through /admin/arti/logging
+    #[arg(long, env = "ARTI_LOG_LEVEL", default_value = "warn", value_parser = parse_arti_log_filter, requires = "generate_arti_config")]
+    pub arti_log_level: String,
+    /// Directory arti also writes rotating log files to in the generated configuration, e.g. on the volume
+    #[arg(long, env = "ARTI_LOG_DIR", requires = "generate_arti_config")]
+    pub arti_log_dir: Option<String>,
+    /// Filter for arti's log files in --arti-log-dir
+    #[arg(long, env = "ARTI_LOG_FILE_LEVEL", default_value = "info", value_parser = parse_arti_log_filter, requires = "arti_log_dir")]
+    pub arti_log_file_level: String,
+    /// How often arti starts a new log file in --arti-log-dir
+    #[arg(long, env = "ARTI_LOG_ROTATE", value_enum, default_value_t = ArtiLogRotate::Daily, requires = "arti_log_dir")]
+    pub arti_log_rotate: ArtiLogRotate,
+    /// MiB arti's log files may take up before the oldest are deleted (0 disables the cap); the newest file is always kept
+    #[arg(
+        long,
+        env = "ARTI_LOG_MAX_MIB",
+        default_value = "64",
+        requires = "arti_log_dir"
+    )]
+    pub arti_log_max_mib: u64,
+    /// Directory arti runs in and relative paths are resolved against (defaults to the current directory)
+    #[arg(long, env = "BASE_DIR")]
+    pub base_dir: Option<PathBuf>,
+    /// Port to bind the first onion service to (0 picks a free one, which needs --generate-arti-config)
+    #[arg(short, long, env = "ONION_PORT", default_value = "3000")]
+    pub onion_port: u16,
+    /// Onion services to serve, by their nickname in the arti configuration; each after the first needs its own port (e.g. `demo,blog=3001`)
+    #[arg(
+        long,
+        env = "ONION_SERVICES",
+        value_delimiter = ',',
+        default_value = "demo"
+    )]
+    pub onion_services: Vec<String>,
+    /// Public hostnames with a site of their own, as `host[@nickname]=site` where site is an http(s):// upstream or a static directory and nickname picks the onion service advertised in Onion-Location (comma separated); other hosts get the default site
+    #[arg(long, env = "PUBLIC_DOMAINS", value_delimiter = ',')]
+    pub public_domains: Vec<String>,
+    /// Port to bind the public endpoint to (0 picks a free one)
+    #[arg(short, long, env = "PUBLIC_PORT", default_value = "8080")]
+    pub public_port: u16,
+    /// HTML page served by the onion endpoint while arti is down (`{eta}` is replaced with the seconds until the next restart)
+    #[arg(long, env = "UNAVAILABLE_PAGE")]
+    pub unavailable_page: Option<PathBuf>,
+    /// Directory holding a `landing.html` template that replaces the built-in landing pages (minijinja syntax; see templates/landing.html for the variables)
+    #[arg(long, env = "TEMPLATES_DIR")]
+    pub templates_dir: Option<PathBuf>,
+    /// Development mode: reload the landing template from --templates-dir when it changes and tell browsers not to cache pages or static files, so edits show on the next reload
+    #[arg(long, env = "DEV")]
+    pub dev: bool,
+    /// Heading of the landing pages
+    #[arg(long, env = "SITE_TITLE", default_value = "Hello!")]
+    pub site_title: String,
+    /// Replaces the landing pages' description of how the visitor is connected
+    #[arg(long, env = "SITE_BODY")]
+    pub site_body: Option<String>,
+    /// Footer added to the landing pages
+    #[arg(long, env = "SITE_FOOTER")]
+    pub site_footer: Option<String>,
+    /// `Server` header sent on onion responses (suppressed when unset)
+    #[arg(long, env = "ONION_SERVER_HEADER", value_parser = parse_header_value)]
+    pub onion_server_header: Option<HeaderValue>,
+    /// `Server` header sent on public responses (suppressed when unset)
+    #[arg(long, env = "PUBLIC_SERVER_HEADER", value_parser = parse_header_value)]
+    pub public_server_header: Option<HeaderValue>,
+    /// `Content-Security-Policy` sent on onion and public responses that don't set their own (empty disables)
+    #[arg(long, env = "CONTENT_SECURITY_POLICY", default_value = DEFAULT_CONTENT_SECURITY_POLICY, value_parser = parse_header_value)]
+    pub content_security_policy: HeaderValue,
+    /// `Referrer-Policy` sent on onion and public responses that don't set their own (empty disables)
+    #[arg(long, env = "REFERRER_POLICY", default_value = "no-referrer", value_parser = parse_header_value)]
+    pub referrer_policy: HeaderValue,
+    /// `X-Frame-Options` sent on onion and public responses that don't set their own (empty disables)
+    #[arg(long, env = "FRAME_OPTIONS", default_value = "DENY", value_parser = parse_header_value)]
+    pub frame_options: HeaderValue,
+    /// `Strict-Transport-Security` sent on public responses that don't set their own (empty disables); never sent over the onion service, which has no TLS
+    #[arg(long, env = "STRICT_TRANSPORT_SECURITY", default_value = "max-age=31536000", value_parser = parse_header_value)]
+    pub strict_transport_security: HeaderValue,
+    /// Methods accepted on every listener, proxied or not; anything else gets a 405 (TRACE and TRACK are always refused)
+    #[arg(
+        long, env = "ALLOWED_METHODS",
+        value_delimiter = ',',
+        default_value = "GET,HEAD,POST,PUT,PATCH,DELETE,OPTIONS",
+        value_parser = parse_allowed_method
+    )]
+    pub allowed_methods: Vec<Method>,
+    /// Requests per second each client may make to the public endpoint, sustained; bursts up to --public-rate-burst are allowed (0 disables the limit)
+    #[arg(long, env = "PUBLIC_RATE_LIMIT", default_value = "0")]
+    pub public_rate_limit: f64,
+    /// Requests a client may make to the public endpoint in a burst before --public-rate-limit applies
+    #[arg(long, env = "PUBLIC_RATE_BURST", default_value = "20", value_parser = clap::value_parser!(u32).range(1..))]
+    pub public_rate_burst: u32,
+    /// Trust the X-Forwarded-For and X-Forwarded-Proto set by the proxy in front of the public listener (Railway's edge): its last X-Forwarded-For entry becomes the client address for logs and rate limits, and its scheme is passed on to the upstream
+    #[arg(long, env = "TRUSTED_PROXY")]
+    pub trusted_proxy: bool,
+    /// Requests each onion listener handles at once, beyond which visitors get a 503; onion clients have no address to limit by (0 disables the limit)
+    #[arg(long, env = "ONION_MAX_CONCURRENT_REQUESTS", default_value = "0")]
+    pub onion_max_concurrent_requests: u32,
+    /// Kibibytes per second each onion connection's responses are paced to, after a one-second burst, so one bulk download can't take the whole circuit budget (0 disables the cap)
+    #[arg(long, env = "ONION_CONNECTION_RATE_KIB", default_value = "0")]
+    pub onion_connection_rate_kib: u64,
+    /// How uniform onion error responses are made, so they don't give away whether the demo pages, a proxied application or static files are served
+    #[arg(long, env = "ONION_ERROR_PROFILE", value_enum, default_value_t = ErrorProfile::Standard)]
+    pub onion_error_profile: ErrorProfile,
+    /// Maximum random delay, in milliseconds, added to onion responses (0 disables jitter)
+    #[arg(long, env = "ONION_JITTER_MS", default_value = "0")]
+    pub onion_jitter_ms: u64,
+    /// Pad onion text responses to a multiple of this many bytes (0 disables padding)
+    #[arg(long, env = "ONION_PAD_BYTES", default_value = "0")]
+    pub onion_pad_bytes: usize,
+    /// Onion paths that jitter and padding apply to (comma separated, all paths when empty)
+    #[arg(long, env = "ONION_SHAPING_PATHS", value_delimiter = ',')]
+    pub onion_shaping_paths: Vec<String>,
+    /// Operator contact published in /.well-known/onion-service.json
+    #[arg(long, env = "OPERATOR_CONTACT")]
+    pub operator_contact: Option<String>,
+    /// Canonical clearnet URL published in /.well-known/onion-service.json
+    #[arg(long, env = "CLEARNET_URL")]
+    pub clearnet_url: Option<String>,
+    /// Clearsigned PGP statement binding the onion address to the clearnet domain, served at /pgp.txt
+    #[arg(long, env = "PGP_STATEMENT")]
+    pub pgp_statement: Option<PathBuf>,
+    /// ASCII-armored PGP public key appended to /pgp.txt
+    #[arg(long, env = "PGP_PUBLIC_KEY", requires = "pgp_statement")]
+    pub pgp_public_key: Option<PathBuf>,
+    /// Run arti purely as a Tor client (e.g. for SOCKS egress) without hosting an onion service
+    #[arg(long, env = "CLIENT_ONLY")]
+    pub client_only: bool,
+    /// Run arti once with this process's stdio instead of supervising it, exiting when it exits so the platform (Railway, systemd) owns restarts; bootstrap progress and the descriptor readiness check are unavailable
+    #[arg(long, env = "FOREGROUND_ARTI")]
+    pub foreground_arti: bool,
+    /// Launch arti even if no Tor directory authority is reachable (e.g. when it only reaches
+    /// the network through bridges or a proxy)
+    #[arg(long, env = "SKIP_EGRESS_CHECK")]
+    pub skip_egress_check: bool,
+    /// How the public endpoint answers search engine crawlers, so the public mirror isn't indexed as the primary site
+    #[arg(long, env = "PUBLIC_CRAWLERS", value_enum, default_value_t = CrawlerPolicy::Allow)]
+    pub public_crawlers: CrawlerPolicy,
+    /// What to do when one listener fails while the other is still serving
+    #[arg(long, env = "ON_LISTENER_FAILURE", value_enum, default_value_t = ListenerFailurePolicy::Abort)]
+    pub on_listener_failure: ListenerFailurePolicy,
+    /// Seconds during which repeats of an identical error are counted instead of logged (0 logs every repeat)
+    #[arg(long, env = "LOG_REPEAT_WINDOW_SECS", default_value = "60")]
+    pub log_repeat_window_secs: u64,
+    /// Directory for diagnostic bundles written on fatal errors (defaults to `crash-dumps` next to arti's state directory)
+    #[arg(long, env = "CRASH_DUMP_DIR", conflicts_with = "state_bucket")]
+    pub crash_dump_dir: Option<PathBuf>,
+    /// Keep the onion address cache and crash dumps in this S3 bucket instead of on the volume
+    #[arg(long, env = "STATE_BUCKET")]
+    pub state_bucket: Option<String>,
+    /// Prefix for every key in the state bucket (e.g. `replica-1/`)
+    #[arg(long, env = "STATE_BUCKET_PREFIX", default_value = "")]
+    pub state_bucket_prefix: String,
+    /// S3-compatible endpoint serving the state bucket (defaults to AWS's endpoint for the region)
+    #[arg(long, env = "S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+    /// Region requests to the state bucket are signed for
+    #[arg(long, env = "AWS_REGION", default_value = "us-east-1")]
+    pub s3_region: String,
+    /// Access key ID for the state bucket
+    #[arg(long, env = "AWS_ACCESS_KEY_ID")]
+    pub s3_access_key_id: Option<String>,
+    /// Secret access key for the state bucket (set via the environment to keep it out of the process list)
+    #[arg(long, env = "AWS_SECRET_ACCESS_KEY", hide_env_values = true)]
+    pub s3_secret_access_key: Option<Secret>,
+    /// What to do when another process modifies the arti configuration or identity keys
+    #[arg(long, env = "ON_EXTERNAL_CHANGE", value_enum, default_value_t = ExternalChangePolicy::Log)]
+    pub on_external_change: ExternalChangePolicy,
+    /// What to do when an onion service's identity key in the keystore is missing or differs from the onion address cached by earlier runs, e.g. because a volume was not mounted
+    #[arg(long, env = "ON_IDENTITY_CHANGE", value_enum, default_value_t = IdentityChangePolicy::Log)]
+    pub on_identity_change: IdentityChangePolicy,
+    /// Child processes (arti, onion address lookups, git) allowed to run at once; arti holds one for as long as it runs
+    #[arg(long, env = "MAX_CHILD_PROCESSES", default_value = "4", value_parser = clap::value_parser!(u64).range(2..))]
+    pub max_child_processes: u64,
+    /// Failures within --arti-failure-window-secs after which arti is no longer relaunched
+    #[arg(long, env = "ARTI_MAX_FAILURES", default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    pub arti_max_failures: u64,
+    /// Rolling window, in seconds, in which arti's failures count towards --arti-max-failures
+    #[arg(long, env = "ARTI_FAILURE_WINDOW_SECS", default_value = "600")]
+    pub arti_failure_window_secs: u64,
+    /// Seconds before relaunching arti after its first failure, doubled for every further failure in the window
+    #[arg(long, env = "ARTI_BACKOFF_BASE_SECS", default_value = "3")]
+    pub arti_backoff_base_secs: u64,
+    /// Upper bound, in seconds, of the backoff before relaunching arti
+    #[arg(long, env = "ARTI_BACKOFF_MAX_SECS", default_value = "120")]
+    pub arti_backoff_max_secs: u64,
+    /// Seconds discovery keeps asking arti for each onion address once it is running
+    #[arg(long, env = "DISCOVERY_TIMEOUT_SECS", default_value = "30")]
+    pub discovery_timeout_secs: u64,
+    /// Find onion addresses only in arti's keystore, without falling back to polling `arti hss onion-address`; the fallback is deprecated and will be removed
+    #[arg(long, env = "KEYSTORE_DISCOVERY_ONLY")]
+    pub keystore_discovery_only: bool,
+    /// Seconds between probes of arti's SOCKS port, restarting arti when it stops answering (0 disables the watchdog)
+    #[arg(
+        long,
+        env = "ARTI_WATCHDOG_SECS",
+        default_value = "0",
+        conflicts_with = "foreground_arti"
+    )]
+    pub arti_watchdog_secs: u64,
+    /// Consecutive failed SOCKS probes after which arti is restarted
+    #[arg(long, env = "ARTI_WATCHDOG_FAILURES", default_value = "3", value_parser = clap::value_parser!(u32).range(1..))]
+    pub arti_watchdog_failures: u32,
+    /// Requests each onion service makes to itself through arti's SOCKS port once arti has bootstrapped, so the first visitor finds the circuits already built (0 disables)
+    #[arg(
+        long,
+        env = "WARM_CIRCUITS",
+        default_value = "0",
+        conflicts_with_all = ["foreground_arti", "client_only"]
+    )]
+    pub warm_circuits: u32,
+    /// Seconds arti and open connections get to finish once shutdown is requested, before arti is killed and the connections are dropped
+    #[arg(long, env = "SHUTDOWN_GRACE_SECS", default_value = "10")]
+    pub shutdown_grace_secs: u64,
+    /// Seconds to wait for another instance to release arti's state directory (0 fails immediately)
+    #[arg(long, env = "WAIT_FOR_LOCK_SECS", default_value = "0")]
+    pub wait_for_lock_secs: u64,
+    /// Identifier prefixed to every log line and recorded in crash dumps (defaults to Railway's replica ID, else random)
+    #[arg(long, env = "INSTANCE_ID")]
+    pub instance_id: Option<String>,
+    /// Serve the /api endpoints on a private listener at this address (e.g. 127.0.0.1:9090, or [::]:9090 for Railway's private network) instead of the public one
+    #[arg(long, env = "ADMIN_LISTEN")]
+    pub admin_listen: Option<SocketAddr>,
+    /// Accept and immediately close TCP connections at this address (e.g. 0.0.0.0:8081), for orchestrators that only support TCP health checks
+    #[arg(long, env = "TCP_HEALTH_LISTEN")]
+    pub tcp_health_listen: Option<SocketAddr>,
+    /// Reverse proxy both listeners to this application (e.g. http://127.0.0.1:8000) instead of serving the demo pages
+    #[arg(long, env = "UPSTREAM_URL")]
+    pub upstream_url: Option<String>,
+    /// PEM bundle of the CAs an https:// upstream is verified against, instead of the bundled web roots (e.g. a private CA)
+    #[arg(long, env = "UPSTREAM_CA_FILE", requires = "upstream_url")]
+    pub upstream_ca_file: Option<PathBuf>,
+    /// SHA-256 public key pins (`sha256/<base64>`, comma separated), one of which a certificate in the https:// upstream's chain must match on top of the usual verification
+    #[arg(
+        long,
+        env = "UPSTREAM_TLS_PINS",
+        value_delimiter = ',',
+        requires = "upstream_url",
+        value_parser = parse_spki_pin
+    )]
+    pub upstream_tls_pins: Vec<[u8; 32]>,
+    /// Accept any certificate from an https:// upstream; for development only, since anyone on the path can then read and alter proxied traffic
+    #[arg(long, env = "UPSTREAM_TLS_INSECURE_SKIP_VERIFY", requires = "upstream_url", conflicts_with_all = ["upstream_ca_file", "upstream_tls_pins"])]
+    pub upstream_tls_insecure_skip_verify: bool,
+    /// Connect to these IP addresses (comma separated) instead of resolving the upstream's hostname, which is still sent as the Host header and TLS server name
+    #[arg(
+        long,
+        env = "UPSTREAM_ADDRESSES",
+        value_delimiter = ',',
+        requires = "upstream_url"
+    )]
+    pub upstream_addresses: Vec<IpAddr>,
+    /// Seconds a lookup of the upstream's hostname is reused before new connections look it up again (0 looks it up for every connection)
+    #[arg(long, env = "UPSTREAM_DNS_REFRESH_SECS", default_value = "30")]
+    pub upstream_dns_refresh_secs: u64,
+    /// Milliseconds connections to the upstream try its preferred address family before racing the other one too (0 tries one address at a time)
+    #[arg(long, env = "UPSTREAM_HAPPY_EYEBALLS_MS", default_value = "300")]
+    pub upstream_happy_eyeballs_ms: u64,
+    /// Refuse to connect unless the upstream's hostname resolves only to private-network addresses (loopback, RFC 1918, CGNAT, link-local, IPv6 unique local); always on for *.railway.internal hosts
+    #[arg(long, env = "UPSTREAM_PRIVATE_NETWORK", requires = "upstream_url")]
+    pub upstream_private_network: bool,
+    /// Serve a checkout of this git repository as static files on both listeners instead of the demo pages
+    #[arg(long, env = "GIT_CONTENT_URL", conflicts_with = "upstream_url")]
+    pub git_content_url: Option<String>,
+    /// Branch of the git content repository to serve
+    #[arg(long, env = "GIT_CONTENT_BRANCH", default_value = "main")]
+    pub git_content_branch: String,
+    /// Directory the git content is checked out into (defaults to `content` next to arti's state directory)
+    #[arg(long, env = "GIT_CONTENT_DIR", requires = "git_content_url")]
+    pub git_content_dir: Option<PathBuf>,
+    /// SSH private key used to fetch the git content (set via the environment to keep it out of the process list)
+    #[arg(
+        long,
+        env = "GIT_DEPLOY_KEY",
+        hide_env_values = true,
+        requires = "git_content_url"
+    )]
+    pub git_deploy_key: Option<Secret>,
+    /// Seconds between pulls of the git content (0 pulls only when the webhook fires)
+    #[arg(long, env = "GIT_PULL_INTERVAL_SECS", default_value = "300")]
+    pub git_pull_interval_secs: u64,
+    /// Enables POST /hooks/content, which refreshes the git content when signed with this secret (GitHub's `X-Hub-Signature-256`)
+    #[arg(
+        long,
+        env = "GIT_WEBHOOK_SECRET",
+        hide_env_values = true,
+        requires = "git_content_url"
+    )]
+    pub git_webhook_secret: Option<Secret>,
+    /// Serve the files in this directory on both listeners instead of the demo pages; paths that match no file get its index.html
+    #[arg(long, env = "STATIC_DIR", conflicts_with_all = ["upstream_url", "git_content_url"])]
+    pub static_dir: Option<PathBuf>,
+    /// Serve the site in this tarball (.tar or .tar.gz) or zip file, a path or http(s):// URL, instead of the demo pages; each new version is unpacked aside and swapped in whole
+    #[arg(long, env = "STATIC_SNAPSHOT", conflicts_with_all = ["upstream_url", "git_content_url", "static_dir"])]
+    pub static_snapshot: Option<String>,
+    /// Directory the static snapshot versions are unpacked into (defaults to `snapshots` next to arti's state directory)
+    #[arg(long, env = "STATIC_SNAPSHOT_DIR", requires = "static_snapshot")]
+    pub static_snapshot_dir: Option<PathBuf>,
+    /// Seconds between checks of the static snapshot for a new version (0 checks only when refreshed over RPC)
+    #[arg(
+        long,
+        env = "STATIC_SNAPSHOT_INTERVAL_SECS",
+        default_value = "300",
+        requires = "static_snapshot"
+    )]
+    pub static_snapshot_interval_secs: u64,
+    /// Log line format; verbosity is controlled with `RUST_LOG` (default `info`)
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// Log every completed request (listener, request ID, method, path, status, latency) at info level instead of debug
+    #[arg(long, env = "ACCESS_LOG")]
+    pub access_log: bool,
+    /// Add a Server-Timing header to proxied responses splitting their latency into time spent waiting for the upstream and time spent in this server, so onion visitors' complaints can be told apart from a slow backend
+    #[arg(long, env = "SERVER_TIMING")]
+    pub server_timing: bool,
+    /// Continue the trace named by an onion client's `traceparent` header instead of starting a new one; by default their trace headers are dropped, since anything they send could tie their requests together
+    #[arg(long, env = "FORWARD_ONION_TRACE_CONTEXT", requires = "upstream_url")]
+    pub forward_onion_trace_context: bool,
+    /// Kilobytes of recent log lines, including arti's, kept in memory for /admin/logs (0 keeps none)
+    #[arg(long, env = "LOG_BUFFER_KB", default_value = "256")]
+    pub log_buffer_kb: usize,
+    /// Bearer token required by the /admin endpoints, which are only served when this is set
+    #[arg(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<Secret>,
+    /// Percentage of onion requests copied, redacted, into a buffer served at /admin/mirror, to debug problems only Tor Browser visitors run into (0 copies none)
+    #[arg(
+        long,
+        env = "ONION_MIRROR_PERCENT",
+        default_value = "0",
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        requires = "admin_token"
+    )]
+    pub onion_mirror_percent: u8,
+    /// Mirrored onion requests kept, oldest dropped first
+    #[arg(
+        long,
+        env = "ONION_MIRROR_REQUESTS",
+        default_value = "100",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub onion_mirror_requests: u32,
+    /// Bytes of each mirrored onion request's body kept, before redaction
+    #[arg(long, env = "ONION_MIRROR_BODY_BYTES", default_value = "1024")]
+    pub onion_mirror_body_bytes: usize,
+    /// Checks that must all pass for /readyz to report ready
+    #[arg(long, env = "READINESS_CHECKS", value_enum, value_delimiter = ',', default_values_t = [HealthCheck::Arti, HealthCheck::Discovery])]
+    pub readiness_checks: Vec<HealthCheck>,
+    /// Consecutive passing rounds of readiness checks before reporting ready
+    #[arg(long, env = "READY_AFTER", default_value = "2", value_parser = clap::value_parser!(u32).range(1..))]
+    pub ready_after: u32,
+    /// Consecutive failing rounds of readiness checks before reporting unready
+    #[arg(long, env = "UNREADY_AFTER", default_value = "3", value_parser = clap::value_parser!(u32).range(1..))]
+    pub unready_after: u32,
+    /// Seconds between rounds of readiness checks
+    #[arg(long, env = "HEALTH_INTERVAL_SECS", default_value = "5")]
+    pub health_interval_secs: u64,
+}
+
+impl CliArgs {
+    /// Makes every relative path absolute against `--base-dir`, itself resolved against the
+    /// current directory, so nothing depends on where the start command happened to run.
+    ///
+    /// A bare `--arti` name is left alone to be looked up on `PATH`.
+    pub fn resolve_paths(mut self) -> Result<Self, Error> {
+        let cwd = env::current_dir()
+            .map_err(|e| Error::Startup(format!("Unable to read the current directory: {e}")))?;
+        let base = match self.base_dir.take() {
+            Some(dir) => cwd.join(dir),
+            None => cwd,
+        };
+        let base = base.canonicalize().unwrap_or(base);
+        // Paths that don't exist yet (e.g. a crash dump directory) are only joined
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                let joined = base.join(&*path);
+                *path = joined.canonicalize().unwrap_or(joined);
+            }
+        };
+
+        resolve(&mut self.config);
+        if let Some(arti) = &mut self.arti {
+            if arti.components().count() > 1 || arti.starts_with(".") {
+                resolve(arti);
+            }
+        }
+        for path in [
+            &mut self.unavailable_page,
+            &mut self.templates_dir,
+            &mut self.pgp_statement,
+            &mut self.pgp_public_key,
+            &mut self.crash_dump_dir,
+            &mut self.git_content_dir,
+            &mut self.static_dir,
+            &mut self.static_snapshot_dir,
+            &mut self.upstream_ca_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            resolve(path);
+        }
+        self.base_dir = Some(base);
+        Ok(self)
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        self.base_dir.as_deref().unwrap_or(Path::new("."))
+    }
+
+    /// arti's state directory; a relative one is relative to the base directory arti runs in.
+    ///
+    /// With `--generate-arti-config` it comes from the options, so it is known before the
+    /// configuration is written.
+    pub fn state_dir(&self) -> Result<PathBuf, Error> {
+        let state_dir = match self.generate_arti_config {
+            true => expand_arti_path(&self.arti_state_dir)?,
+            false => arti_state_dir(&self.config)?,
+        };
+        Ok(self.base_dir().join(state_dir))
+    }
+}
+
+/// Output format for log events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, for Railway's log ingestion
+    Json,
+}
+
+/// Policy applied when a single listener fails at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListenerFailurePolicy {
+    /// Shut down the remaining listener and arti, exiting with an error
+    Abort,
+    /// Keep serving on the remaining listener; exit with an error once it stops
+    Continue,
+}
+
+/// How the onion listener shapes error responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ErrorProfile {
+    /// Errors raised by this server (unknown paths, refused methods, an unreachable upstream) share one plain-text shape; the proxied application's own errors and the outage page pass through
+    Standard,
+    /// Every error, the application's and the outage page included, gets the shared shape and is held back to a common minimum response time
+    Strict,
+}
+
+/// How the public endpoint answers search engine crawlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CrawlerPolicy {
+    /// Serve crawlers the same responses as everyone else
+    Allow,
+    /// Serve crawlers a stub page whose canonical link points at the onion service
+    Canonical,
+    /// Answer crawlers with 403 Forbidden
+    Forbid,
+}
+
+/// Reaction to another process modifying arti's files while the server runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExternalChangePolicy {
+    /// Log a warning and keep running
+    Log,
+    /// Log a warning and shut down
+    Shutdown,
+}
+
+/// How often arti starts a new log file (its `rotate` setting).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtiLogRotate {
+    Daily,
+    Hourly,
+    /// Keep writing a single file, which the size cap then can't trim
+    Never,
+}
+
+impl ArtiLogRotate {
+    pub fn name(self) -> &'static str {
+        match self {
+            ArtiLogRotate::Daily => "daily",
+            ArtiLogRotate::Hourly => "hourly",
+            ArtiLogRotate::Never => "never",
+        }
+    }
+}
+
+/// Checks a log filter in the `RUST_LOG` syntax arti reads from its `[logging]` section.
+pub fn parse_arti_log_filter(filter: &str) -> Result<String, String> {
+    if filter.trim().is_empty() {
+        return Err("the log filter is empty".to_string());
+    }
+    tracing_subscriber::EnvFilter::builder()
+        .parse(filter)
+        .map(|_| filter.to_string())
+        .map_err(|e| format!("invalid log filter {filter:?}: {e}"))
+}
+
+/// Reaction to finding an onion service identity other than the one earlier runs served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IdentityChangePolicy {
+    /// Log an error and publish the new identity
+    Log,
+    /// Refuse to start, so arti never publishes a descriptor for the new identity
+    Refuse,
+}
+
+/// A credential taken from the options, printed as `<redacted>` by `Debug` so it stays out of
+/// crash dumps.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl std::ops::Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret(value)
+    }
+}
+
+fn parse_header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|e| format!("invalid header value: {e}"))
+}
+
+/// Parses an HPKP-style pin: the base64 SHA-256 digest of a certificate's SubjectPublicKeyInfo,
+/// as printed by `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl
+/// dgst -sha256 -binary | base64`.
+pub fn parse_spki_pin(value: &str) -> Result<[u8; 32], String> {
+    use base64::Engine;
+
+    let digest = value.trim();
+    let digest = digest.strip_prefix("sha256/").unwrap_or(digest);
+    base64::engine::general_purpose::STANDARD
+        .decode(digest)
+        .ok()
+        .and_then(|digest| digest.try_into().ok())
+        .ok_or_else(|| format!("{value:?} is not a base64 SHA-256 digest"))
+}
+
+pub fn parse_allowed_method(value: &str) -> Result<Method, String> {
+    let method = Method::from_bytes(value.trim().to_ascii_uppercase().as_bytes())
+        .map_err(|e| format!("invalid method: {e}"))?;
+    match method.as_str() {
+        // Both echo the request back, cookies and credentials included (cross-site tracing)
+        "TRACE" | "TRACK" => Err(format!("{method} can't be allowed")),
+        _ => Ok(method),
+    }
+}
+
+/// Parses the command line `args`, taking defaults for the server options from `--config-file`
+/// (or `CONFIG_FILE`) when one is given.
+///
+/// The file's values become the options' defaults, so environment variables and flags still
+/// override them.
+pub fn parse_cli(args: Vec<std::ffi::OsString>) -> Result<Cli, Error> {
+    let path = args
+        .iter()
+        .enumerate()
+        .find_map(|(i, arg)| {
+            let arg = arg.to_str()?;
+            match arg.strip_prefix("--config-file") {
+                Some("") => args.get(i + 1).map(PathBuf::from),
+                Some(value) => value.strip_prefix('=').map(PathBuf::from),
+                None => None,
+            }
+        })
+        .or_else(|| env::var_os("CONFIG_FILE").map(PathBuf::from));
+    let mut command = Cli::command()
+        .mut_subcommand("serve", explicit_switches)
+        .mut_subcommand("check-config", explicit_switches);
+    command = explicit_switches(command);
+    if let Some(path) = path {
+        for (id, values) in read_config_file(&path)? {
+            let apply = |arg: clap::Arg| arg.required(false).default_values(values.clone());
+            command = command
+                .mut_arg(&id, apply)
+                .mut_subcommand("serve", |serve| serve.mut_arg(&id, apply))
+                .mut_subcommand("check-config", |check| check.mut_arg(&id, apply));
+        }
+    }
+    let matches = command
+        .try_get_matches_from_mut(args)
+        .map_err(Error::Usage)?;
+    // The environment also fills in the top-level server options, which must be left out when
+    // a subcommand is given
+    let cli = match matches.subcommand_name() {
+        Some(_) => CliCommand::from_arg_matches(&matches).map(|command| Cli {
+            command: Some(command),
+            serve: None,
+        }),
+        None => Cli::from_arg_matches(&matches),
+    };
+    cli.map_err(|e| Error::Usage(e.format(&mut command)))
+}
+
+/// Lets every switch also take a value, as in `--access-log=false`, so one turned on by the
+/// config file or the environment can be turned off again.
+fn explicit_switches(command: clap::Command) -> clap::Command {
+    let switches: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| matches!(arg.get_action(), clap::ArgAction::SetTrue))
+        .map(|arg| arg.get_id().clone())
+        .collect();
+    switches.iter().fold(command, |command, id| {
+        command.mut_arg(id, |arg| {
+            arg.action(clap::ArgAction::Set)
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("true")
+                .default_value("false")
+        })
+    })
+}
+
+/// Reads a `--config-file` into the ids of the options it sets and their values as they would
+/// be written on the command line.
+fn read_config_file(path: &Path) -> Result<Vec<(String, Vec<String>)>, Error> {
+    let invalid =
+        |e: String| Error::Startup(format!("Invalid config file {}: {e}", path.display()));
+    let table: toml::Table = std::fs::read_to_string(path)
+        .map_err(|e| {
+            Error::Startup(format!(
+                "Unable to read config file {}: {e:?}",
+                path.display()
+            ))
+        })?
+        .parse()
+        .map_err(|e: toml::de::Error| invalid(e.to_string()))?;
+    let command = CliArgs::augment_args(clap::Command::new("serve"));
+    let value = |key: &str, value: &toml::Value| match value {
+        toml::Value::String(value) => Ok(value.clone()),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(invalid(format!(
+            "{key} must be a string, number, or boolean"
+        ))),
+    };
+    table
+        .iter()
+        .map(|(key, entry)| {
+            let long = key.replace('_', "-");
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long.as_str()) && long != "config-file")
+                .ok_or_else(|| invalid(format!("unknown option {key}")))?;
+            let values = match entry {
+                toml::Value::Array(entries) => entries
+                    .iter()
+                    .map(|entry| value(key, entry))
+                    .collect::<Result<_, _>>()?,
+                entry => vec![value(key, entry)?],
+            };
+            Ok((arg.get_id().to_string(), values))
+        })
+        .collect()
+}
+
+/// What the `client-auth` subcommand does.
+#[derive(Debug, Subcommand)]
+pub enum ClientAuthAction {
+    /// Print each authorized client and its key
+    List,
+    /// Authorize a client, replacing the key it had
+    Add {
+        /// Name the client is known by, e.g. `alice`
+        name: String,
+        /// The client's x25519 public key, as `descriptor:x25519:<base32>`
+        key: String,
+    },
+    /// Revoke a client's authorization
+    Remove { name: String },
+}
+
+/// What the `keys` subcommand does.
+#[derive(Debug, Subcommand)]
+pub enum KeysAction {
+    /// Write arti's keystore to a passphrase-encrypted backup
+    Export {
+        /// File to write the backup to, or `-` for standard output
+        file: PathBuf,
+    },
+    /// Restore a backup made with `keys export` into arti's keystore
+    Import {
+        /// Backup to restore, or `-` for standard input
+        file: PathBuf,
+        /// Replace keys that differ from the backup's
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Output format of the `config-schema` subcommand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SchemaFormat {
+    Json,
+    Markdown,
+}
+
+/// A single configuration option, as reported by `config-schema`.
+#[derive(Debug, Serialize)]
+struct ConfigOption {
+    flag: String,
+    short: Option<char>,
+    env: Option<String>,
+    /// Type of each value
+    #[serde(rename = "type")]
+    value_type: &'static str,
+    /// Whether the option takes several values, by repeating it or, with a delimiter, in one
+    multiple: bool,
+    delimiter: Option<char>,
+    default: Option<String>,
+    possible_values: Vec<String>,
+    required: bool,
+    description: String,
+}
+
+/// Describes the configuration surface by introspecting the clap definition of [`CliArgs`].
+///
+/// Generated rather than hand-written so new options can't be forgotten.
+fn config_schema() -> Vec<ConfigOption> {
+    use std::any::TypeId;
+
+    let command = CliArgs::augment_args(clap::Command::new("serve"));
+    command
+        .get_arguments()
+        .map(|arg| {
+            let parser = arg.get_value_parser();
+            let possible_values: Vec<String> = arg
+                .get_possible_values()
+                .iter()
+                .map(|value| value.get_name().to_string())
+                .collect();
+            let types = [
+                (TypeId::of::<PathBuf>(), "path"),
+                (TypeId::of::<u8>(), "u8"),
+                (TypeId::of::<u16>(), "u16"),
+                (TypeId::of::<u32>(), "u32"),
+                (TypeId::of::<u64>(), "u64"),
+                (TypeId::of::<usize>(), "usize"),
+                (TypeId::of::<f64>(), "f64"),
+                (TypeId::of::<IpAddr>(), "ip-address"),
+                (TypeId::of::<SocketAddr>(), "socket-address"),
+            ];
+            let value_type = if !arg.get_action().takes_values() {
+                "bool"
+            } else if !possible_values.is_empty() {
+                "enum"
+            } else {
+                types
+                    .iter()
+                    .find(|(id, _)| parser.type_id() == *id)
+                    .map_or("string", |(_, name)| name)
+            };
+            let multiple = matches!(arg.get_action(), clap::ArgAction::Append);
+            let default = arg
+                .get_default_values()
+                .iter()
+                .map(|value| value.to_string_lossy())
+                .collect::<Vec<_>>();
+
+            ConfigOption {
+                flag: format!("--{}", arg.get_long().unwrap_or(arg.get_id().as_str())),
+                short: arg.get_short(),
+                env: arg.get_env().map(|env| env.to_string_lossy().into_owned()),
+                value_type,
+                multiple,
+                delimiter: arg.get_value_delimiter(),
+                default: (!default.is_empty()).then(|| default.join(",")),
+                possible_values,
+                required: arg.is_required_set(),
+                description: arg.get_help().map(ToString::to_string).unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+pub fn print_config_schema(format: SchemaFormat) {
+    let options = config_schema();
+    match format {
+        SchemaFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&options).expect("schema serializes")
+            );
+        }
+        SchemaFormat::Markdown => {
+            println!("| Option | Env | Type | Default | Description |");
+            println!("|---|---|---|---|---|");
+            for option in options {
+                let flag = match option.short {
+                    Some(short) => format!("`-{short}`, `{}`", option.flag),
+                    None => format!("`{}`", option.flag),
+                };
+                let mut value_type = match option.possible_values.as_slice() {
+                    [] => option.value_type.to_string(),
+                    values => values.join(" \\| "),
+                };
+                if option.multiple {
+                    value_type = match option.delimiter {
+                        Some(delimiter) => format!("list of {value_type}, `{delimiter}` separated"),
+                        None => format!("list of {value_type}, repeated"),
+                    };
+                }
+                println!(
+                    "| {flag} | {} | {value_type} | {} | {}{} |",
+                    option.env.map(|env| format!("`{env}`")).unwrap_or_default(),
+                    option
+                        .default
+                        .map(|default| format!("`{default}`"))
+                        .unwrap_or_default(),
+                    option.description.replace('|', "\\|"),
+                    if option.required { " (required)" } else { "" },
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_schema_reports_value_types_and_lists() {
+        let schema = config_schema();
+        let option = |flag: &str| schema.iter().find(|option| option.flag == flag).unwrap();
+        for (flag, value_type) in [
+            ("--onion-max-concurrent-requests", "u32"),
+            ("--onion-mirror-percent", "u8"),
+            ("--public-rate-limit", "f64"),
+            ("--upstream-addresses", "ip-address"),
+            ("--admin-listen", "socket-address"),
+            ("--access-log", "bool"),
+        ] {
+            assert_eq!(option(flag).value_type, value_type, "{flag}");
+        }
+        assert!(option("--upstream-addresses").multiple);
+        assert_eq!(option("--upstream-addresses").delimiter, Some(','));
+        assert!(!option("--upstream-url").multiple);
+    }
+
+    #[test]
+    fn config_file_values_yield_to_flags() {
+        let path = env::temp_dir().join(format!("config-{}.toml", rand::random::<u32>()));
+        std::fs::write(
+            &path,
+            "config = \"arti.toml\"\npublic-port = 9000\nonion_port = 4000\n\
+             client-only = true\nreadiness-checks = [\"arti\", \"self-test\"]\n",
+        )
+        .unwrap();
+        let parse = |extra: &[&str]| {
+            let args = ["arti-axum-railway", "--config-file", path.to_str().unwrap()]
+                .iter()
+                .chain(extra)
+                .map(Into::into)
+                .collect();
+            parse_cli(args).unwrap().serve.unwrap()
+        };
+
+        let args = parse(&[]);
+        assert_eq!(args.config, PathBuf::from("arti.toml"));
+        assert_eq!((args.public_port, args.onion_port), (9000, 4000));
+        assert!(args.client_only);
+        assert_eq!(
+            args.readiness_checks,
+            [HealthCheck::Arti, HealthCheck::SelfTest]
+        );
+        assert_eq!(parse(&["-p", "9100"]).public_port, 9100);
+        assert!(!parse(&["--client-only=false"]).client_only);
+        assert!(parse(&["--client-only"]).client_only);
+        assert!(!parse(&[]).skip_egress_check);
+        assert!(matches!(
+            parse_cli(vec!["arti-axum-railway".into(), "--no-such-flag".into()]),
+            Err(Error::Usage(_))
+        ));
+
+        std::fs::write(&path, "no-such-option = 1\n").unwrap();
+        assert!(read_config_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
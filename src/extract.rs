@@ -0,0 +1,194 @@
+//! Extractors for handlers added with [`run_cli_with_routes`](crate::run_cli_with_routes).
+//!
+//! They read what the wrapper knows about a request and the onion service behind it, so
+//! embedding handlers don't need to reach into the server's internals. Outside those routes
+//! the wrapper's context is missing and extraction fails with a 500.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, FromRequestParts, OptionalFromRequestParts};
+use axum::http::{request::Parts, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use crate::socks::HttpClient;
+use crate::supervisor::{ArtiStatus, BootstrapState};
+use crate::AppState;
+
+/// What the wrapper attaches to requests for embedded routes.
+#[derive(Clone)]
+pub(crate) struct Context {
+    pub(crate) origin: OriginMarker,
+    pub(crate) state: Arc<AppState>,
+}
+
+impl Context {
+    fn from_parts(parts: &Parts) -> Result<&Self, ExtractRejection> {
+        parts
+            .extensions
+            .get::<Self>()
+            .ok_or(ExtractRejection::MissingContext)
+    }
+}
+
+/// Why an extractor in this module failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractRejection {
+    /// The handler wasn't mounted through [`run_cli_with_routes`](crate::run_cli_with_routes)
+    MissingContext,
+    /// [`CircuitInfo`] was asked for on a request that didn't arrive over Tor
+    NotOnion,
+    /// [`TorHttp`] was asked for, but arti is embedded or its SOCKS port is disabled
+    NoSocksPort,
+}
+
+impl IntoResponse for ExtractRejection {
+    fn into_response(self) -> Response {
+        match self {
+            ExtractRejection::MissingContext => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Handler is not mounted behind the onion service wrapper\n",
+            ),
+            ExtractRejection::NotOnion => (StatusCode::NOT_FOUND, "Not Found\n"),
+            ExtractRejection::NoSocksPort => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "arti has no SOCKS port to make requests through\n",
+            ),
+        }
+        .into_response()
+    }
+}
+
+/// The listener a request arrived on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginMarker {
+    /// The public (clearnet) listener
+    Public,
+    /// An onion service's listener, by the service's nickname
+    Onion { service: String },
+}
+
+impl OriginMarker {
+    pub fn is_onion(&self) -> bool {
+        matches!(self, OriginMarker::Onion { .. })
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for OriginMarker {
+    type Rejection = ExtractRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Context::from_parts(parts)?.origin.clone())
+    }
+}
+
+/// A snapshot of arti and the onion services, taken when the request was extracted.
+#[derive(Debug, Clone)]
+pub struct OnionStatus {
+    pub arti: ArtiStatus,
+    /// Times the supervisor has relaunched arti
+    pub restarts: u64,
+    pub bootstrap: BootstrapState,
+    /// The readiness verdict behind `/readyz`
+    pub ready: bool,
+    /// Address of the service the request is for: the one it arrived on, or the primary service
+    /// on the public listener; `None` until discovered
+    pub address: Option<String>,
+    /// Every service's address discovered so far, by nickname
+    pub addresses: BTreeMap<String, String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for OnionStatus {
+    type Rejection = ExtractRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Context { origin, state } = Context::from_parts(parts)?;
+        let addresses = state.onion_addresses.read().clone();
+        let service = match origin {
+            OriginMarker::Onion { service } => Some(service.as_str()),
+            OriginMarker::Public => state
+                .onion_services
+                .first()
+                .map(|service| service.nickname.as_str()),
+        };
+        Ok(OnionStatus {
+            arti: *state.arti_status.borrow(),
+            restarts: state.arti_restarts.load(Ordering::Relaxed),
+            bootstrap: state.bootstrap.read().clone(),
+            ready: state.health.borrow().ready,
+            address: service.and_then(|service| addresses.get(service).cloned()),
+            addresses,
+        })
+    }
+}
+
+/// What is known about the Tor stream an onion request arrived on.
+///
+/// arti doesn't tell the service which circuit a stream came over, but each stream gets its
+/// own connection to the onion listener and lives on a single circuit: requests with the same
+/// `stream` share a circuit. Nothing here identifies the visitor.
+///
+/// Fails with a 404 on the public listener; use `Option<CircuitInfo>` on routes serving both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitInfo {
+    /// Nickname of the onion service the stream was made to
+    pub service: String,
+    /// The service's onion address, once discovered
+    pub onion_address: Option<String>,
+    /// arti's end of the local connection carrying the stream
+    pub stream: Option<SocketAddr>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for CircuitInfo {
+    type Rejection = ExtractRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        <Self as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+            .await?
+            .ok_or(ExtractRejection::NotOnion)
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for CircuitInfo {
+    type Rejection = ExtractRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let Context { origin, state } = Context::from_parts(parts)?;
+        let OriginMarker::Onion { service } = origin else {
+            return Ok(None);
+        };
+        Ok(Some(CircuitInfo {
+            service: service.clone(),
+            onion_address: state.onion_addresses.read().get(service).cloned(),
+            stream: parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(peer)| *peer),
+        }))
+    }
+}
+
+/// An HTTP client whose requests go over Tor, through the supervised arti's SOCKS port.
+///
+/// Lets a handler call other onion services, e.g. `http://<address>.onion/api`; see
+/// [`socks::http_client`](crate::socks::http_client).
+#[derive(Clone)]
+pub struct TorHttp(pub HttpClient);
+
+impl<S: Send + Sync> FromRequestParts<S> for TorHttp {
+    type Rejection = ExtractRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Context { state, .. } = Context::from_parts(parts)?;
+        let client = state
+            .tor_http
+            .clone()
+            .ok_or(ExtractRejection::NoSocksPort)?;
+        Ok(TorHttp(client))
+    }
+}
@@ -0,0 +1,304 @@
+//! Liveness and readiness: the checks behind `/readyz`, how many passes or failures in a row it
+//! takes to change the verdict, and the loop that runs them.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Json, Response},
+};
+use clap::ValueEnum;
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use crate::schedule::Schedule;
+use crate::signals::ShutdownSignal;
+use crate::supervisor::ArtiStatus;
+use crate::{AppState, Format};
+
+/// Liveness probe: answering at all means the process is alive.
+pub async fn healthz_handler(format: Format) -> Response {
+    format.render(
+        "<!DOCTYPE html><title>Health</title><p>ok</p>".to_string(),
+        "ok\n".to_string(),
+        &serde_json::json!({ "status": "ok" }),
+    )
+}
+
+/// A condition contributing to readiness, selected with `--readiness-checks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HealthCheck {
+    /// arti is running
+    Arti,
+    /// Every onion service's address is known (always passes in client-only mode)
+    Discovery,
+    /// The upstream application accepts connections (always passes without --upstream-url)
+    Upstream,
+    /// The public listener answers its own /healthz
+    SelfTest,
+    /// The onion service descriptor is published and its refresh isn't overdue (always passes in
+    /// client-only mode; needs the arti binary, whose log it is read from)
+    Descriptor,
+}
+
+impl HealthCheck {
+    fn name(&self) -> &'static str {
+        match self {
+            HealthCheck::Arti => "arti",
+            HealthCheck::Discovery => "discovery",
+            HealthCheck::Upstream => "upstream",
+            HealthCheck::SelfTest => "self-test",
+            HealthCheck::Descriptor => "descriptor",
+        }
+    }
+}
+
+/// Consecutive probe rounds needed to change readiness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthThresholds {
+    /// Passing rounds before an unready service becomes ready
+    pub ready_after: u32,
+    /// Failing rounds before a ready service becomes unready
+    pub unready_after: u32,
+}
+
+/// Readiness with hysteresis, so one slow probe doesn't flap Railway's healthcheck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HealthState {
+    /// Not ready; `passes` rounds in a row have passed
+    Unready { passes: u32 },
+    /// Ready; `failures` rounds in a row have failed
+    Ready { failures: u32 },
+}
+
+impl HealthState {
+    /// Returns the state after a probe round that `passed` or not.
+    fn observe(self, passed: bool, thresholds: HealthThresholds) -> Self {
+        match (self, passed) {
+            (HealthState::Unready { passes }, true) if passes + 1 >= thresholds.ready_after => {
+                HealthState::Ready { failures: 0 }
+            }
+            (HealthState::Unready { passes }, true) => HealthState::Unready { passes: passes + 1 },
+            (HealthState::Unready { .. }, false) => HealthState::Unready { passes: 0 },
+            (HealthState::Ready { failures }, false)
+                if failures + 1 >= thresholds.unready_after =>
+            {
+                HealthState::Unready { passes: 0 }
+            }
+            (HealthState::Ready { failures }, false) => HealthState::Ready {
+                failures: failures + 1,
+            },
+            (HealthState::Ready { .. }, true) => HealthState::Ready { failures: 0 },
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        matches!(self, HealthState::Ready { .. })
+    }
+}
+
+/// Latest readiness verdict, published by [`monitor_health`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthReport {
+    pub ready: bool,
+    /// Outcome of each check in the latest round
+    pub checks: BTreeMap<&'static str, bool>,
+}
+
+/// Point-in-time probes behind each [`HealthCheck`].
+pub struct HealthProbes {
+    pub state: Arc<AppState>,
+    /// `host:port` of the upstream application, in proxy mode
+    pub upstream: Option<String>,
+    pub self_test: Uri,
+    pub client: Client<HttpConnector, Body>,
+}
+
+/// How long a single network probe may take before it counts as failed.
+const HEALTH_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl HealthProbes {
+    async fn check(&self, check: HealthCheck) -> bool {
+        match check {
+            HealthCheck::Arti => *self.state.arti_status.borrow() == ArtiStatus::Running,
+            HealthCheck::Discovery => {
+                let addresses = self.state.onion_addresses.read();
+                self.state.client_only
+                    || (self.state.onion_services.iter())
+                        .all(|service| addresses.contains_key(&service.nickname))
+            }
+            HealthCheck::Upstream => match &self.upstream {
+                Some(upstream) => matches!(
+                    tokio::time::timeout(
+                        HEALTH_PROBE_TIMEOUT,
+                        tokio::net::TcpStream::connect(upstream)
+                    )
+                    .await,
+                    Ok(Ok(_))
+                ),
+                None => true,
+            },
+            HealthCheck::SelfTest => {
+                let request = axum::http::Request::get(self.self_test.clone())
+                    .body(Body::empty())
+                    .expect("valid self-test request");
+                matches!(
+                    tokio::time::timeout(HEALTH_PROBE_TIMEOUT, self.client.request(request)).await,
+                    Ok(Ok(response)) if response.status().is_success()
+                )
+            }
+            HealthCheck::Descriptor => {
+                let descriptor = *self.state.descriptor.read();
+                self.state.client_only
+                    || (descriptor.published.is_some()
+                        && descriptor.overdue(SystemTime::now()).is_none())
+            }
+        }
+    }
+}
+
+/// Runs every configured check each `interval` and feeds the outcome through [`HealthState`],
+/// publishing the verdict on `report` until shutdown.
+pub async fn monitor_health(
+    probes: HealthProbes,
+    checks: Vec<HealthCheck>,
+    thresholds: HealthThresholds,
+    interval: Duration,
+    report: watch::Sender<HealthReport>,
+    mut shutdown: ShutdownSignal,
+) {
+    let mut schedule = Schedule::every(interval).starting_now();
+    let mut health = HealthState::Unready { passes: 0 };
+    let mut descriptor_overdue = false;
+    while schedule.tick(&mut shutdown).await {
+        // Warned about whether or not the descriptor check gates readiness
+        let overdue = probes.state.descriptor.read().overdue(SystemTime::now());
+        if overdue.is_some() != descriptor_overdue {
+            match overdue {
+                Some(late) => {
+                    warn!(
+                        overdue_secs = late.as_secs(),
+                        "onion service descriptor republish is overdue"
+                    )
+                }
+                None => info!("onion service descriptor republished"),
+            }
+            descriptor_overdue = overdue.is_some();
+        }
+
+        let mut results = BTreeMap::new();
+        for check in &checks {
+            results.insert(check.name(), probes.check(*check).await);
+        }
+        let passed = results.values().all(|passed| *passed);
+        let next = health.observe(passed, thresholds);
+        if next.is_ready() != health.is_ready() {
+            let failing: Vec<_> = results
+                .iter()
+                .filter(|(_, passed)| !**passed)
+                .map(|(name, _)| *name)
+                .collect();
+            if next.is_ready() {
+                info!("ready");
+            } else {
+                warn!(failing = ?failing, "no longer ready");
+            }
+        }
+        health = next;
+        report.send_replace(HealthReport {
+            ready: health.is_ready(),
+            checks: results,
+        });
+    }
+}
+
+/// Readiness as reported by `/readyz`.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    #[serde(flatten)]
+    health: HealthReport,
+    arti_status: &'static str,
+    /// Address of the primary onion service
+    onion_address: Option<String>,
+    onion_services: BTreeMap<String, Option<String>>,
+}
+
+/// Readiness probe: 503 until the `--readiness-checks` have passed `--ready-after` rounds in a
+/// row, and again once they fail `--unready-after` rounds in a row.
+///
+/// Railway's healthcheck only gates deploys on the HTTP side otherwise; this lets it wait for
+/// the Tor side to actually come up.
+pub async fn readyz_handler(State(state): State<Arc<AppState>>) -> Response {
+    let health = state.health.borrow().clone();
+    let status = if health.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(Readiness {
+            health,
+            arti_status: state.arti_status.borrow().name(),
+            onion_address: state.onion_address(),
+            onion_services: state.onion_service_addresses(),
+        }),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: HealthThresholds = HealthThresholds {
+        ready_after: 2,
+        unready_after: 3,
+    };
+
+    /// Feeds probe rounds through the health state machine, returning readiness after each.
+    fn readiness_after(rounds: &[bool]) -> Vec<bool> {
+        let mut health = HealthState::Unready { passes: 0 };
+        rounds
+            .iter()
+            .map(|passed| {
+                health = health.observe(*passed, THRESHOLDS);
+                health.is_ready()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn health_needs_consecutive_passes_to_become_ready() {
+        assert_eq!(
+            readiness_after(&[true, false, true, true, true]),
+            [false, false, false, true, true]
+        );
+    }
+
+    #[test]
+    fn health_tolerates_failures_below_the_threshold() {
+        assert_eq!(
+            readiness_after(&[true, true, false, false, true, false, false, false]),
+            [false, true, true, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn health_with_unit_thresholds_follows_every_round() {
+        let thresholds = HealthThresholds {
+            ready_after: 1,
+            unready_after: 1,
+        };
+        let ready = HealthState::Unready { passes: 0 }.observe(true, thresholds);
+        assert!(ready.is_ready());
+        assert!(!ready.observe(false, thresholds).is_ready());
+    }
+}
@@ -0,0 +1,237 @@
+//! Getting ready to supervise arti: finding its binary, locking its state directory so two
+//! instances never share it, and watching its configuration and keys for changes made by other
+//! processes.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::arti_logging::ArtiLogging;
+use crate::config::ExternalChangePolicy;
+use crate::signals::{Shutdown, ShutdownReason};
+use crate::{Diagnostics, Error, LogThrottle};
+
+/// Files another process has no business touching while the server runs: the arti
+/// configuration and the identity keys already in the keystore.
+///
+/// Keys arti creates later (e.g. on first launch) are deliberately left out, so its own writes
+/// aren't reported.
+fn watched_arti_files(config: &Path, state_dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = config.canonicalize().into_iter().collect();
+    let services = std::fs::read_dir(state_dir.join("keystore").join("hss")).ok();
+    for service in services.into_iter().flatten().flatten() {
+        for key in std::fs::read_dir(service.path())
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            if key.file_name().to_string_lossy().starts_with("ks_hs_id") {
+                files.extend(key.path().canonicalize());
+            }
+        }
+    }
+    files
+}
+
+/// Reports external changes to the arti configuration or identity keys, a sign of another
+/// process or a misconfigured volume mount fighting with the wrapper.
+pub async fn watch_arti_files(
+    config: PathBuf,
+    state_dir: PathBuf,
+    policy: ExternalChangePolicy,
+    own_writes: Option<Arc<ArtiLogging>>,
+    shutdown: Shutdown,
+    log: Arc<LogThrottle>,
+    diagnostics: Arc<Diagnostics>,
+) {
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    let files = watched_arti_files(&config, &state_dir);
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })
+    .and_then(|mut watcher| {
+        // Watch the parent directories, since editors and atomic writers replace files
+        let mut dirs: Vec<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+        dirs.dedup();
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        Ok(watcher)
+    });
+    let _watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("unable to watch arti files for external changes: {e}");
+            return;
+        }
+    };
+
+    while let Some(event) = rx.recv().await {
+        let event: notify::Event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                log.error(format!("file watcher error: {e}"));
+                continue;
+            }
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            continue;
+        }
+        for path in event.paths.iter().filter(|path| files.contains(path)) {
+            // The configuration as rewritten through /admin/arti/logging
+            if let Some(logging) = &own_writes {
+                if std::fs::read_to_string(path).is_ok_and(|contents| logging.wrote(&contents)) {
+                    continue;
+                }
+            }
+            let message = format!(
+                "warning: {} was changed by another process ({:?})",
+                path.display(),
+                event.kind
+            );
+            diagnostics.event(&message);
+            log.error(message);
+            if policy == ExternalChangePolicy::Shutdown
+                && shutdown.trigger(ShutdownReason::ExternalChange)
+            {
+                error!("shutting down after an external change to arti's files");
+            }
+        }
+    }
+}
+
+/// Name of the lock file held in arti's state directory while the server runs.
+const INSTANCE_LOCK_FILE: &str = "arti-axum-railway.lock";
+
+/// Locks arti's state directory so two wrapper instances (e.g. during overlapping deploys)
+/// can't supervise the same onion service at once.
+///
+/// Waits up to `wait` for another instance to release the lock. The lock is held until the
+/// returned file is dropped; the holder's PID is written into it for the error message.
+pub async fn acquire_instance_lock(
+    state_dir: &Path,
+    wait: Duration,
+) -> Result<std::fs::File, Error> {
+    use fs4::fs_std::FileExt;
+    use std::io::{Read, Seek, Write};
+
+    let mut dirs = std::fs::DirBuilder::new();
+    dirs.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut dirs, 0o700);
+    dirs.create(state_dir)
+        .map_err(|e| Error::Startup(format!("Unable to create {}: {e:?}", state_dir.display())))?;
+
+    let path = state_dir.join(INSTANCE_LOCK_FILE);
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&path)
+        .map_err(|e| Error::Startup(format!("Unable to open {}: {e:?}", path.display())))?;
+
+    let deadline = Instant::now() + wait;
+    let mut announced = false;
+    loop {
+        let locked = file
+            .try_lock_exclusive()
+            .map_err(|e| Error::Startup(format!("Unable to lock {}: {e:?}", path.display())))?;
+        if locked {
+            break;
+        }
+
+        if Instant::now() >= deadline {
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            let holder = match holder.trim() {
+                "" => String::new(),
+                pid => format!(" (pid {pid})"),
+            };
+            return Err(Error::Startup(format!(
+                "another instance{holder} is supervising {}; pass --wait-for-lock-secs to wait for it",
+                state_dir.display()
+            )));
+        }
+        if !announced {
+            info!(lock = %path.display(), "waiting for another instance to release the lock");
+            announced = true;
+        }
+        sleep(Duration::from_secs(1).min(deadline - Instant::now())).await;
+    }
+
+    let mut record_pid = || -> std::io::Result<()> {
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        file.flush()
+    };
+    record_pid()
+        .map_err(|e| Error::Startup(format!("Unable to write {}: {e:?}", path.display())))?;
+    Ok(file)
+}
+
+/// Finds the arti binary: `--arti`/`ARTI_BIN` if given, else `./arti`, else `arti` on `PATH`.
+///
+/// Bare names (`arti`, `arti-1.4`) are looked up on `PATH` like a shell would. Fails at
+/// startup, rather than on every relaunch, if the result is missing or not executable.
+pub fn resolve_arti_binary(configured: Option<&Path>, base_dir: &Path) -> Result<PathBuf, Error> {
+    fn is_executable(path: &Path) -> bool {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return false;
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+        }
+        #[cfg(not(unix))]
+        metadata.is_file()
+    }
+
+    let search_path = |name: &Path| {
+        env::var_os("PATH").and_then(|paths| {
+            env::split_paths(&paths)
+                .map(|dir| dir.join(name))
+                .find(|candidate| is_executable(candidate))
+        })
+    };
+
+    let binary = match configured {
+        Some(name) if name.components().count() == 1 && !name.starts_with(".") => search_path(name)
+            .ok_or_else(|| {
+                Error::Startup(format!("arti binary {} not found on PATH", name.display()))
+            })?,
+        Some(path) => {
+            if !path.exists() {
+                return Err(Error::Startup(format!(
+                    "arti binary {} does not exist",
+                    path.display()
+                )));
+            }
+            if !is_executable(path) {
+                return Err(Error::Startup(format!(
+                    "arti binary {} is not executable",
+                    path.display()
+                )));
+            }
+            path.to_path_buf()
+        }
+        None => Some(base_dir.join("arti"))
+            .filter(|local| is_executable(local))
+            .or_else(|| search_path(Path::new("arti")))
+            .ok_or_else(|| {
+                Error::Startup(format!(
+                    "no arti binary in {} or on PATH; pass --arti or set ARTI_BIN",
+                    base_dir.display()
+                ))
+            })?,
+    };
+    info!(binary = %binary.display(), "using arti binary");
+    Ok(binary)
+}
@@ -0,0 +1,326 @@
+//! Sampled copies of onion requests, kept in memory for `/admin/mirror` so a problem only Tor
+//! Browser visitors run into can be looked at without logging their traffic.
+//!
+//! Anything that could identify a visitor or carry a secret is redacted before a copy is kept:
+//! header values outside a short list describing the browser's behaviour, path segments that
+//! look like identifiers, query and form values and bare entries, and the values of JSON bodies;
+//! other bodies are only described. Nothing is written to disk
+//! or to the logs.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, request::Parts, Request};
+use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// Headers whose values are kept, since they describe how the browser behaves rather than who
+/// is using it. Tor Browser sends the same values for every user.
+const SHOWN_HEADERS: &[&str] = &[
+    "accept",
+    "accept-encoding",
+    "cache-control",
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-type",
+    "dnt",
+    "pragma",
+    "priority",
+    "sec-fetch-dest",
+    "sec-fetch-mode",
+    "sec-fetch-site",
+    "sec-fetch-user",
+    "sec-gpc",
+    "te",
+    "transfer-encoding",
+    "upgrade",
+    "upgrade-insecure-requests",
+    "user-agent",
+];
+
+/// Stands in for a redacted value.
+const REDACTED: &str = "[redacted]";
+
+/// A redacted copy of a request.
+#[derive(Debug, Clone, Serialize)]
+pub struct MirroredRequest {
+    /// Seconds since the Unix epoch
+    pub at: u64,
+    pub listener: &'static str,
+    pub method: String,
+    /// Path, with query values, bare query entries, and segments that look like identifiers
+    /// redacted
+    pub path: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    /// Redacted start of the body
+    pub body: String,
+    /// Whether the body went on past what was kept
+    pub truncated: bool,
+    /// Status of the response, once it started
+    pub status: Option<u16>,
+}
+
+/// The most recent mirrored requests, oldest dropped first.
+pub struct RequestMirror {
+    /// Fraction of requests copied
+    rate: f64,
+    capacity: usize,
+    /// Body bytes kept per request, before redaction
+    body_bytes: usize,
+    requests: Mutex<VecDeque<MirroredRequest>>,
+}
+
+impl RequestMirror {
+    pub fn new(percent: u8, capacity: usize, body_bytes: usize) -> Self {
+        Self {
+            rate: f64::from(percent.min(100)) / 100.0,
+            capacity,
+            body_bytes,
+            requests: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether to mirror the next request.
+    pub fn sampled(&self) -> bool {
+        rand::random_bool(self.rate)
+    }
+
+    /// Copies `request`, reading the start of its body, and returns it with the body intact.
+    pub async fn capture(
+        &self,
+        request: Request<Body>,
+        listener: &'static str,
+    ) -> (Request<Body>, MirroredRequest) {
+        let (parts, body) = request.into_parts();
+        let mut stream = body.into_data_stream();
+        let mut chunks: Vec<Result<Bytes, axum::Error>> = Vec::new();
+        let mut kept = Vec::new();
+        let mut seen = 0;
+        while seen <= self.body_bytes {
+            match stream.next().await {
+                Some(Ok(chunk)) => {
+                    seen += chunk.len();
+                    let room = self.body_bytes - kept.len().min(self.body_bytes);
+                    kept.extend_from_slice(&chunk[..chunk.len().min(room)]);
+                    chunks.push(Ok(chunk));
+                }
+                Some(Err(e)) => {
+                    chunks.push(Err(e));
+                    break;
+                }
+                None => break,
+            }
+        }
+        let truncated = seen > self.body_bytes;
+        let copy = MirroredRequest {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            listener,
+            method: parts.method.to_string(),
+            path: redact_uri(&parts),
+            version: format!("{:?}", parts.version),
+            headers: redact_headers(&parts),
+            body: redact_body(&parts, &kept, truncated),
+            truncated,
+            status: None,
+        };
+        let body = Body::from_stream(futures::stream::iter(chunks).chain(stream));
+        (Request::from_parts(parts, body), copy)
+    }
+
+    pub fn record(&self, request: MirroredRequest) {
+        let mut requests = self.requests.lock();
+        if requests.len() == self.capacity {
+            requests.pop_front();
+        }
+        requests.push_back(request);
+    }
+
+    /// The mirrored requests, oldest first.
+    pub fn requests(&self) -> Vec<MirroredRequest> {
+        self.requests.lock().iter().cloned().collect()
+    }
+}
+
+/// Longest path segment kept as it is.
+const SHOWN_SEGMENT_LEN: usize = 32;
+
+fn redact_uri(parts: &Parts) -> String {
+    let path = parts
+        .uri
+        .path()
+        .split('/')
+        .map(|segment| match shown_segment(segment) {
+            true => segment,
+            false => REDACTED,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    match parts.uri.query() {
+        Some(query) => format!("{path}?{}", redact_pairs(query)),
+        None => path,
+    }
+}
+
+/// Whether a path segment reads like part of a route (`static`, `app.js`) rather than an
+/// identifier or token: short, and without digits or escapes.
+fn shown_segment(segment: &str) -> bool {
+    segment.len() <= SHOWN_SEGMENT_LEN
+        && segment
+            .bytes()
+            .all(|byte| byte.is_ascii_alphabetic() || matches!(byte, b'-' | b'_' | b'.'))
+}
+
+/// `a=1&b` as `a=[redacted]&[redacted]`: a bare entry may itself be a secret, such as a
+/// reset token.
+fn redact_pairs(pairs: &str) -> String {
+    pairs
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) => format!("{key}={REDACTED}"),
+            None if pair.is_empty() => String::new(),
+            None => REDACTED.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn redact_headers(parts: &Parts) -> Vec<(String, String)> {
+    parts
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match SHOWN_HEADERS.contains(&name.as_str()) {
+                true => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                false => REDACTED.to_string(),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Keeps the keys of form and complete JSON bodies; anything else is only described.
+fn redact_body(parts: &Parts, body: &[u8], truncated: bool) -> String {
+    if body.is_empty() {
+        return String::new();
+    }
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown type");
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if essence == "application/x-www-form-urlencoded" {
+        if let Ok(form) = std::str::from_utf8(body) {
+            return redact_pairs(form);
+        }
+    }
+    let json = essence == "application/json" || essence.ends_with("+json");
+    if json && !truncated {
+        if let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(body) {
+            redact_json(&mut value);
+            return value.to_string();
+        }
+    }
+    format!("[{} bytes of {content_type}]", body.len())
+}
+
+/// Replaces every string, number and boolean in `value`, keeping object keys and the shape.
+fn redact_json(value: &mut serde_json::Value) {
+    use serde_json::Value;
+
+    match value {
+        Value::Null => {}
+        Value::Bool(_) | Value::Number(_) | Value::String(_) => {
+            *value = Value::String(REDACTED.to_string())
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        Value::Object(fields) => fields.values_mut().for_each(redact_json),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn capture(mirror: &RequestMirror, request: Request<Body>) -> (Bytes, MirroredRequest) {
+        let (request, copy) = mirror.capture(request, "onion").await;
+        let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (body, copy)
+    }
+
+    #[tokio::test]
+    async fn copies_are_redacted_and_the_body_passed_on_whole() {
+        let mirror = RequestMirror::new(100, 2, 16);
+        let request = Request::post("/login?next=/account&debug")
+            .header(
+                header::USER_AGENT,
+                "Mozilla/5.0 (Windows NT 10.0; rv:128.0)",
+            )
+            .header(header::COOKIE, "session=secret")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from("user=alice&password=hunter2&remember=1"))
+            .unwrap();
+        let (body, copy) = capture(&mirror, request).await;
+        assert_eq!(&body[..], b"user=alice&password=hunter2&remember=1");
+        assert_eq!(copy.path, "/login?next=[redacted]&[redacted]");
+        assert!(copy
+            .headers
+            .contains(&("cookie".to_string(), REDACTED.to_string())));
+        assert!(copy
+            .headers
+            .iter()
+            .any(|(_, value)| value.contains("rv:128.0")));
+        assert_eq!(copy.body, "user=[redacted]&[redacted]");
+        assert!(copy.truncated);
+
+        let request = Request::post("/api")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"a":[1,null]}"#))
+            .unwrap();
+        let (_, copy) = capture(&mirror, request).await;
+        assert_eq!(copy.body, r#"{"a":["[redacted]",null]}"#);
+
+        let request = Request::get("/reset/3f9a2b7c/confirm/?d41d8cd98f00b204")
+            .body(Body::empty())
+            .unwrap();
+        let (_, copy) = capture(&mirror, request).await;
+        assert_eq!(copy.path, "/reset/[redacted]/confirm/?[redacted]");
+
+        let request = Request::post("/upload")
+            .header(header::CONTENT_TYPE, "image/png")
+            .body(Body::from("\u{89}PNG"))
+            .unwrap();
+        let (_, copy) = capture(&mirror, request).await;
+        assert_eq!(copy.body, "[5 bytes of image/png]");
+    }
+
+    #[tokio::test]
+    async fn only_the_most_recent_copies_are_kept() {
+        let mirror = RequestMirror::new(100, 2, 0);
+        assert!(mirror.sampled());
+        assert!(!RequestMirror::new(0, 2, 0).sampled());
+        for path in ["/a", "/b", "/c"] {
+            let (_, copy) = capture(&mirror, Request::get(path).body(Body::empty()).unwrap()).await;
+            mirror.record(copy);
+        }
+        let paths: Vec<_> = mirror
+            .requests()
+            .into_iter()
+            .map(|copy| copy.path)
+            .collect();
+        assert_eq!(paths, ["/b", "/c"]);
+    }
+}
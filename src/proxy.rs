@@ -0,0 +1,569 @@
+//! Proxy mode: forwarding requests to `--upstream-url` over HTTP or verified TLS, with the
+//! hop-by-hop headers dropped and the `X-Forwarded-*` ones set.
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::CliArgs;
+use crate::{resolve, ClientAddress, Error, Format, LogThrottle, Proxied, UpstreamTiming};
+
+/// Headers that describe a single connection and must not be forwarded by a proxy (RFC 9110 §7.6.1).
+const HOP_BY_HOP_HEADERS: [HeaderName; 8] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+pub const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+pub const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// Cookie the upstream application's pages set from a script, e.g.
+/// `document.cookie = "js=1; path=/; SameSite=Strict"`. Tor Browser's security levels leave the
+/// `Accept` headers alone, so a script leaving a mark is the only reliable sign that scripts run.
+const SCRIPTS_COOKIE: &str = "js=1";
+
+const X_CLIENT_SCRIPTS: HeaderName = HeaderName::from_static("x-client-scripts");
+
+/// Whether a client runs the scripts a page includes, as far as its request tells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scripts {
+    /// Carries [`SCRIPTS_COOKIE`]
+    Enabled,
+    /// A browser that hasn't shown it runs scripts: they are disabled, as at Tor Browser's
+    /// Safest level, or it hasn't loaded a page that sets the cookie yet
+    Unknown,
+    /// Not a browser, going by an `Accept` header without HTML
+    None,
+}
+
+impl Scripts {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let cookie = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .any(|cookie| cookie.trim() == SCRIPTS_COOKIE);
+        match (cookie, Format::from_headers(headers)) {
+            (true, _) => Scripts::Enabled,
+            (false, Format::Html) => Scripts::Unknown,
+            (false, _) => Scripts::None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Scripts::Enabled => "enabled",
+            Scripts::Unknown => "unknown",
+            Scripts::None => "none",
+        }
+    }
+}
+
+/// Removes hop-by-hop headers, including any the `Connection` header nominates.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let nominated: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in HOP_BY_HOP_HEADERS.iter().chain(&nominated) {
+        headers.remove(name);
+    }
+}
+
+/// How an https:// upstream's certificate is checked.
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamTls {
+    /// CAs trusted instead of the bundled web roots
+    pub ca_file: Option<PathBuf>,
+    /// SPKI digests, one of which the chain must contain
+    pub pins: Vec<[u8; 32]>,
+    /// Accept any certificate
+    pub insecure: bool,
+}
+
+impl UpstreamTls {
+    pub fn from_args(args: &CliArgs) -> Self {
+        Self {
+            ca_file: args.upstream_ca_file.clone(),
+            pins: args.upstream_tls_pins.clone(),
+            insecure: args.upstream_tls_insecure_skip_verify,
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        self.ca_file.is_none() && self.pins.is_empty() && !self.insecure
+    }
+
+    fn client_config(&self) -> Result<rustls::ClientConfig, Error> {
+        use rustls::pki_types::{pem::PemObject, CertificateDer};
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = rustls::RootCertStore::empty();
+        match &self.ca_file {
+            Some(path) => {
+                let unreadable = |e: &dyn std::fmt::Display| {
+                    Error::Startup(format!("Unable to read CAs from {}: {e}", path.display()))
+                };
+                for cert in CertificateDer::pem_file_iter(path).map_err(|e| unreadable(&e))? {
+                    roots
+                        .add(cert.map_err(|e| unreadable(&e))?)
+                        .map_err(|e| unreadable(&e))?;
+                }
+                if roots.is_empty() {
+                    return Err(unreadable(&"no certificates found"));
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        let webpki = match self.insecure {
+            true => None,
+            false => Some(
+                rustls::client::WebPkiServerVerifier::builder_with_provider(
+                    Arc::new(roots),
+                    provider.clone(),
+                )
+                .build()
+                .map_err(|e| Error::Startup(format!("Unable to verify the upstream: {e}")))?,
+            ),
+        };
+        let verifier = UpstreamVerifier {
+            webpki,
+            pins: self.pins.clone(),
+            algorithms: provider.signature_verification_algorithms,
+        };
+        Ok(rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::Startup(format!("Unable to configure TLS: {e}")))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth())
+    }
+}
+
+/// Verifies the upstream's chain against the configured CAs (unless verification is disabled),
+/// then checks it against the pins.
+#[derive(Debug)]
+struct UpstreamVerifier {
+    /// `None` with `--upstream-tls-insecure-skip-verify`
+    webpki: Option<Arc<rustls::client::WebPkiServerVerifier>>,
+    pins: Vec<[u8; 32]>,
+    algorithms: rustls::crypto::WebPkiSupportedAlgorithms,
+}
+
+impl rustls::client::danger::ServerCertVerifier for UpstreamVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        server_name: &rustls::pki_types::ServerName<'_>,
+        ocsp_response: &[u8],
+        now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        use sha2::Digest;
+
+        if let Some(webpki) = &self.webpki {
+            webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| webpki::EndEntityCert::try_from(cert).ok())
+            .any(|cert| {
+                let digest: [u8; 32] = sha2::Sha256::digest(cert.subject_public_key_info()).into();
+                self.pins.contains(&digest)
+            });
+        if !self.pins.is_empty() && !pinned {
+            return Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// How the upstream's hostname is turned into the addresses connected to.
+#[derive(Debug, Clone)]
+pub struct UpstreamDns {
+    /// Addresses connected to instead of resolving the hostname
+    pub addresses: Vec<IpAddr>,
+    /// How long a lookup is reused
+    refresh: Duration,
+    /// Head start of the preferred address family before the other one is raced too
+    happy_eyeballs: Option<Duration>,
+    /// Refuse addresses outside a private network
+    private_network: bool,
+}
+
+impl Default for UpstreamDns {
+    fn default() -> Self {
+        Self {
+            addresses: Vec::new(),
+            refresh: Duration::from_secs(30),
+            happy_eyeballs: Some(Duration::from_millis(300)),
+            private_network: false,
+        }
+    }
+}
+
+impl UpstreamDns {
+    pub fn from_args(args: &CliArgs) -> Self {
+        Self {
+            addresses: args.upstream_addresses.clone(),
+            refresh: Duration::from_secs(args.upstream_dns_refresh_secs),
+            happy_eyeballs: Some(Duration::from_millis(args.upstream_happy_eyeballs_ms))
+                .filter(|timeout| !timeout.is_zero()),
+            private_network: args.upstream_private_network,
+        }
+    }
+
+    pub fn private_network(&self, upstream: &Uri) -> bool {
+        self.private_network || railway_internal(upstream)
+    }
+
+    /// A connector for `upstream`. Hosts on Railway's private network are only ever connected to
+    /// there, whatever public DNS might say about them, over IPv6 from this service's address on
+    /// that network.
+    fn connector(&self, upstream: &Uri) -> Result<HttpConnector<resolve::Resolver>, Error> {
+        let host = upstream.host().unwrap_or_default();
+        let literal = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .ok();
+        let private_network = self.private_network(upstream);
+        match literal {
+            Some(_) if !self.addresses.is_empty() => {
+                return Err(Error::Startup(format!(
+                    "Pinned upstream addresses need an upstream hostname, not {host}"
+                )))
+            }
+            Some(ip) if private_network && !resolve::is_private(ip) => {
+                return Err(Error::Startup(format!(
+                    "Upstream address {ip} is not on a private network"
+                )))
+            }
+            _ => {}
+        }
+        let railway = railway_internal(upstream);
+        let resolver = resolve::Resolver::new(
+            self.addresses.clone(),
+            self.refresh,
+            private_network,
+            railway,
+        )
+        .map_err(|e| Error::Startup(format!("Invalid upstream addresses: {e}")))?;
+        let mut http = HttpConnector::new_with_resolver(resolver);
+        http.enforce_http(false);
+        http.set_happy_eyeballs_timeout(self.happy_eyeballs);
+        let ipv6 = self.addresses.is_empty() || self.addresses.iter().any(IpAddr::is_ipv6);
+        if railway && ipv6 {
+            // Bound to an IPv6 address, the connector only tries the upstream's IPv6 addresses
+            match resolve::source_address(resolve::RAILWAY_PRIVATE_NETWORK.into()) {
+                Some(local) if resolve::is_private(local) => {
+                    info!(%local, "connecting to the upstream over Railway's private network; it must listen on :: to be reachable");
+                    http.set_local_address(Some(local));
+                }
+                _ => warn!(
+                    %upstream,
+                    "no address on Railway's private network; is private networking enabled for this service?"
+                ),
+            }
+        }
+        Ok(http)
+    }
+}
+
+/// Whether `upstream` is a host on Railway's private network.
+fn railway_internal(upstream: &Uri) -> bool {
+    upstream
+        .host()
+        .is_some_and(|host| host.ends_with(".railway.internal"))
+}
+
+/// Forwards requests to the application at `--upstream-url`, streaming bodies both ways.
+pub struct ReverseProxy {
+    client: Client<hyper_rustls::HttpsConnector<HttpConnector<resolve::Resolver>>, Body>,
+    pub upstream: Uri,
+    log: Arc<LogThrottle>,
+}
+
+impl ReverseProxy {
+    /// The application is expected to sit on localhost or Railway's private network, usually
+    /// over plain `http://`; `https://` upstreams are verified as `tls` says.
+    pub fn new(
+        upstream: &str,
+        tls: &UpstreamTls,
+        dns: &UpstreamDns,
+        log: Arc<LogThrottle>,
+    ) -> Result<Self, Error> {
+        let upstream: Uri = upstream
+            .parse()
+            .map_err(|e| Error::Startup(format!("Invalid upstream URL {upstream:?}: {e}")))?;
+        let https = match upstream.scheme_str() {
+            Some("http") => false,
+            Some("https") => true,
+            _ => {
+                return Err(Error::Startup(format!(
+                    "Upstream URL {upstream} must be an absolute http:// or https:// URL"
+                )))
+            }
+        };
+        if upstream.authority().is_none() {
+            return Err(Error::Startup(format!(
+                "Upstream URL {upstream} must be an absolute http:// or https:// URL"
+            )));
+        }
+        if !https && !tls.is_default() {
+            return Err(Error::Startup(format!(
+                "Upstream TLS options need an https:// upstream, not {upstream}"
+            )));
+        }
+        if tls.insecure {
+            warn!(
+                %upstream,
+                "upstream certificates are NOT verified (--upstream-tls-insecure-skip-verify); \
+                 anyone on the path can read and alter proxied traffic"
+            );
+        }
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls.client_config()?)
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(dns.connector(&upstream)?);
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+        Ok(Self {
+            client,
+            upstream,
+            log,
+        })
+    }
+
+    /// Maps a request path onto the upstream, keeping any path prefix the upstream URL has.
+    fn target(&self, request: &Uri) -> Result<Uri, axum::http::Error> {
+        let prefix = self.upstream.path().trim_end_matches('/');
+        let path_and_query = request
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        Uri::builder()
+            .scheme(self.upstream.scheme().expect("validated in new").clone())
+            .authority(self.upstream.authority().expect("validated in new").clone())
+            .path_and_query(format!("{prefix}{path_and_query}"))
+            .build()
+    }
+
+    /// Proxies `request` arriving on the `origin` listener.
+    ///
+    /// The original `Host` is preserved so the application can build its own links. Onion
+    /// requests never get an `X-Forwarded-For`: their peer is always arti on loopback, and the
+    /// client is anonymous by design.
+    pub async fn forward(self: Arc<Self>, origin: &'static str, mut request: Request) -> Response {
+        let target = match self.target(request.uri()) {
+            Ok(target) => target,
+            Err(e) => {
+                self.log
+                    .error(format!("cannot map {} upstream: {e}", request.uri()));
+                return StatusCode::BAD_GATEWAY.into_response();
+            }
+        };
+        *request.uri_mut() = target;
+
+        let scripts = Scripts::from_headers(request.headers());
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| peer.ip());
+        let https = request
+            .extensions()
+            .get::<ClientAddress>()
+            .is_some_and(|client| client.https);
+        let headers = request.headers_mut();
+        strip_hop_by_hop(headers);
+        if let Some(host) = headers.get(header::HOST).cloned() {
+            headers.insert(X_FORWARDED_HOST, host);
+        }
+        headers.insert(X_CLIENT_SCRIPTS, HeaderValue::from_static(scripts.as_str()));
+        match origin {
+            "onion" => {
+                headers.remove(X_FORWARDED_FOR);
+                headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("http"));
+            }
+            _ => {
+                // Appended to whatever the proxy in front set, trusted or not, as proxies do
+                if let Some(peer) = peer {
+                    let forwarded_for = match headers.get(X_FORWARDED_FOR) {
+                        Some(existing) => {
+                            format!("{}, {peer}", existing.to_str().unwrap_or_default())
+                        }
+                        None => peer.to_string(),
+                    };
+                    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+                        headers.insert(X_FORWARDED_FOR, value);
+                    }
+                }
+                // Kept only from a trusted proxy, so the application's redirects stay on https
+                let proto = if https { "https" } else { "http" };
+                headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+            }
+        }
+
+        let started = Instant::now();
+        let result = self.client.request(request).await;
+        let timing = UpstreamTiming(started.elapsed());
+        let mut response = match result {
+            Ok(response) => {
+                let (mut parts, body) = response.into_parts();
+                strip_hop_by_hop(&mut parts.headers);
+                parts.extensions.insert(Proxied);
+                Response::from_parts(parts, Body::new(body))
+            }
+            Err(e) => {
+                // The connector's error only says "Connect"; the cause (refused, bad certificate)
+                // is further down the chain
+                let mut message = format!("upstream request to {} failed: {e}", self.upstream);
+                let mut source = std::error::Error::source(&e);
+                while let Some(cause) = source {
+                    message.push_str(&format!(": {cause}"));
+                    source = cause.source();
+                }
+                self.log.error(message);
+                (
+                    StatusCode::BAD_GATEWAY,
+                    "Upstream application unavailable\n",
+                )
+                    .into_response()
+            }
+        };
+        response.extensions_mut().insert(timing);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    use crate::config::{parse_spki_pin, Cli};
+
+    #[test]
+    fn upstream_tls_options_need_an_https_upstream() {
+        let pin = "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        assert_eq!(parse_spki_pin(pin).unwrap()[..4], [0xe3, 0xb0, 0xc4, 0x42]);
+        assert!(parse_spki_pin("sha256/c2hvcnQ=").is_err());
+
+        let log = || Arc::new(LogThrottle::new(Duration::ZERO));
+        let dns = UpstreamDns::default();
+        let pinned = UpstreamTls {
+            pins: vec![parse_spki_pin(pin).unwrap()],
+            ..UpstreamTls::default()
+        };
+        assert!(ReverseProxy::new("http://127.0.0.1:8000", &pinned, &dns, log()).is_err());
+        assert!(ReverseProxy::new("https://internal.example", &pinned, &dns, log()).is_ok());
+        let missing_ca = UpstreamTls {
+            ca_file: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..UpstreamTls::default()
+        };
+        assert!(ReverseProxy::new("https://internal.example", &missing_ca, &dns, log()).is_err());
+    }
+
+    #[test]
+    fn scripts_are_known_to_run_only_with_the_scripts_cookie() {
+        let headers = |pairs: &[(HeaderName, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.append(name, value.parse().unwrap());
+            }
+            headers
+        };
+        let html = (header::ACCEPT, "text/html,*/*;q=0.8");
+        let cookie = (header::COOKIE, "theme=dark; js=1");
+        assert_eq!(
+            Scripts::from_headers(&headers(&[html.clone(), cookie])),
+            Scripts::Enabled
+        );
+        assert_eq!(
+            Scripts::from_headers(&headers(&[html.clone(), (header::COOKIE, "js=0")])),
+            Scripts::Unknown
+        );
+        assert_eq!(Scripts::from_headers(&headers(&[])), Scripts::None);
+    }
+
+    #[test]
+    fn railway_internal_upstreams_are_only_reached_over_the_private_network() {
+        let log = || Arc::new(LogThrottle::new(Duration::ZERO));
+        let tls = UpstreamTls::default();
+        let public = UpstreamDns {
+            addresses: vec!["203.0.113.7".parse().unwrap()],
+            ..UpstreamDns::default()
+        };
+        assert!(ReverseProxy::new("http://app.example", &tls, &public, log()).is_ok());
+        assert!(ReverseProxy::new("http://app.railway.internal", &tls, &public, log()).is_err());
+        assert!(ReverseProxy::new("http://127.0.0.1:8000", &tls, &public, log()).is_err());
+
+        let private = UpstreamDns {
+            private_network: true,
+            ..UpstreamDns::default()
+        };
+        assert!(ReverseProxy::new("http://[fd12::1]:8000", &tls, &private, log()).is_ok());
+        assert!(ReverseProxy::new("http://203.0.113.7", &tls, &private, log()).is_err());
+
+        let cli = Cli::try_parse_from([
+            "arti-axum-railway",
+            "-c",
+            "arti.toml",
+            "--upstream-addresses",
+            "10.0.0.1,fd12::1",
+        ]);
+        assert!(cli.is_err(), "pinned addresses without an upstream");
+    }
+}
@@ -0,0 +1,90 @@
+//! Renders `/qr.png`: the `qrcode` crate encodes the symbol and this writes it out as a 1-bit
+//! greyscale PNG, which keeps an image library out of the build.
+
+use std::io::Write;
+
+use qrcode::{Color, EcLevel, QrCode};
+
+/// Modules of blank margin around the symbol, which scanners need.
+const QUIET_ZONE: usize = 4;
+
+/// Encodes `data` at error correction level M and renders it `scale` pixels per module, or
+/// `None` if it is too long for a QR code.
+pub fn png(data: &[u8], scale: usize) -> Option<Vec<u8>> {
+    let code = QrCode::with_error_correction_level(data, EcLevel::M).ok()?;
+    Some(render(code.width(), &code.to_colors(), scale))
+}
+
+/// Writes `size`×`size` modules, row by row, as a PNG with the quiet zone added.
+fn render(size: usize, modules: &[Color], scale: usize) -> Vec<u8> {
+    let width = (size + 2 * QUIET_ZONE) * scale;
+    let row_bytes = width.div_ceil(8);
+    let mut pixels = Vec::with_capacity((row_bytes + 1) * width);
+    for py in 0..width {
+        // Filter type: none
+        pixels.push(0);
+        let mut row = vec![0xffu8; row_bytes];
+        for px in 0..width {
+            let (x, y) = (px / scale, py / scale);
+            let dark = (QUIET_ZONE..QUIET_ZONE + size).contains(&x)
+                && (QUIET_ZONE..QUIET_ZONE + size).contains(&y)
+                && modules[(y - QUIET_ZONE) * size + x - QUIET_ZONE] == Color::Dark;
+            if dark {
+                row[px / 8] &= !(0x80 >> (px % 8));
+            }
+        }
+        pixels.extend_from_slice(&row);
+    }
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&pixels).expect("writing to memory");
+    let compressed = encoder.finish().expect("writing to memory");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    // Bit depth 1, greyscale, deflate, no filtering method extensions, no interlacing
+    header.extend_from_slice(&[1, 0, 0, 0, 0]);
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, data) in [
+        (b"IHDR", &header),
+        (b"IDAT", &compressed),
+        (b"IEND", &Vec::new()),
+    ] {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(kind);
+        crc.update(data);
+        png.extend_from_slice(&crc.finalize().to_be_bytes());
+    }
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pngs_have_a_quiet_zone_and_valid_chunks() {
+        let url = "http://abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvwxyz2345.onion/";
+        let png = png(url.as_bytes(), 2).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"));
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+        // Version 5 at level M, 37 modules across
+        assert_eq!(width as usize, (37 + 2 * QUIET_ZONE) * 2);
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+
+        // Dark modules are cleared bits, offset by the margin
+        let modules = [Color::Light, Color::Dark, Color::Dark, Color::Light];
+        let png = render(2, &modules, 1);
+        let pixels = flate2::read::ZlibDecoder::new(&png[8 + 12 + 13 + 8..png.len() - 16]);
+        let pixels: Vec<u8> = std::io::Read::bytes(pixels).map(Result::unwrap).collect();
+        let row = |y: usize| &pixels[y * 3..y * 3 + 3];
+        assert_eq!(row(0), [0, 0xff, 0xff]);
+        assert_eq!(row(QUIET_ZONE), [0, 0xfb, 0xff]);
+        assert_eq!(row(QUIET_ZONE + 1), [0, 0xf7, 0xff]);
+
+        assert!(super::png(&[b'a'; 4000], 1).is_none());
+    }
+}
@@ -0,0 +1,271 @@
+//! How proxy mode turns its upstream's hostname into addresses: pinned addresses instead of DNS,
+//! answers reused for a refresh interval, and a refusal to connect anywhere outside the private
+//! network when the upstream is expected on one, such as Railway's.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use hyper_util::client::legacy::connect::dns::Name;
+use parking_lot::Mutex;
+use tracing::warn;
+
+/// Railway's private network, fd12::/16, which `*.railway.internal` hosts are reached over.
+pub const RAILWAY_PRIVATE_NETWORK: Ipv6Addr = Ipv6Addr::new(0xfd12, 0, 0, 0, 0, 0, 0, 0);
+
+/// The local address the kernel routes traffic to `destination` from, found by connecting a UDP
+/// socket, which sends nothing.
+pub fn source_address(destination: IpAddr) -> Option<IpAddr> {
+    let unspecified = match destination {
+        IpAddr::V4(_) => IpAddr::from([0, 0, 0, 0]),
+        IpAddr::V6(_) => IpAddr::from([0; 16]),
+    };
+    let socket = UdpSocket::bind((unspecified, 0)).ok()?;
+    socket.connect((destination, 9)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Whether `ip` is only reachable inside a private network: loopback, RFC 1918, shared (CGNAT)
+/// and link-local IPv4, and loopback, unique local and link-local IPv6.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || (a == 100 && b & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private(ip.into()),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/// A lookup kept for reuse.
+struct Answer {
+    at: Instant,
+    addrs: Vec<IpAddr>,
+}
+
+struct Inner {
+    /// Addresses used instead of asking DNS
+    pinned: Vec<IpAddr>,
+    /// How long an answer is reused before the name is looked up again
+    refresh: Duration,
+    /// Refuse answers with any address outside a private network
+    private_only: bool,
+    /// Try IPv6 addresses first
+    prefer_ipv6: bool,
+    answers: Mutex<HashMap<String, Answer>>,
+}
+
+/// Resolves upstream hostnames for the proxy's connector.
+#[derive(Clone)]
+pub struct Resolver(Arc<Inner>);
+
+impl Resolver {
+    pub fn new(
+        mut pinned: Vec<IpAddr>,
+        refresh: Duration,
+        private_only: bool,
+        prefer_ipv6: bool,
+    ) -> Result<Self, String> {
+        if private_only {
+            if let Some(ip) = pinned.iter().find(|ip| !is_private(**ip)) {
+                return Err(format!(
+                    "pinned upstream address {ip} is not on a private network"
+                ));
+            }
+        }
+        if prefer_ipv6 {
+            pinned.sort_by_key(|ip| !ip.is_ipv6());
+        }
+        Ok(Self(Arc::new(Inner {
+            pinned,
+            refresh,
+            private_only,
+            prefer_ipv6,
+            answers: Mutex::new(HashMap::new()),
+        })))
+    }
+
+    /// The addresses to try for `host`, in order. A failed lookup falls back to the last answer,
+    /// however old, so a DNS hiccup doesn't take the upstream down with it.
+    pub async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        if !self.0.pinned.is_empty() {
+            return Ok(self.0.pinned.clone());
+        }
+        let cached = self
+            .0
+            .answers
+            .lock()
+            .get(host)
+            .map(|answer| (answer.at.elapsed() < self.0.refresh, answer.addrs.clone()));
+        if let Some((true, addrs)) = cached {
+            return Ok(addrs);
+        }
+        match self.lookup(host).await {
+            Ok(addrs) => {
+                let answer = Answer {
+                    at: Instant::now(),
+                    addrs: addrs.clone(),
+                };
+                self.0.answers.lock().insert(host.to_string(), answer);
+                Ok(addrs)
+            }
+            Err(e) => match cached {
+                Some((_, addrs)) => {
+                    warn!(host, error = %e, "upstream lookup failed, reusing the last answer");
+                    Ok(addrs)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        let mut addrs = Vec::new();
+        for addr in tokio::net::lookup_host((host, 0)).await? {
+            if !addrs.contains(&addr.ip()) {
+                addrs.push(addr.ip());
+            }
+        }
+        if self.0.private_only {
+            if let Some(ip) = addrs.iter().find(|ip| !is_private(**ip)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "{host} resolved to {ip}, outside the private network it is expected on"
+                    ),
+                ));
+            }
+        }
+        if self.0.prefer_ipv6 {
+            addrs.sort_by_key(|ip| !ip.is_ipv6());
+        }
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{host} has no addresses"),
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+impl tower_service::Service<Name> for Resolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let addrs = resolver.resolve(name.as_str()).await?;
+            // Port 0 is replaced with the upstream URL's port by the connector
+            let addrs: Vec<_> = addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_addresses_are_told_apart_from_public_ones() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "100.64.0.1",
+            "169.254.1.1",
+            "::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(is_private(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "203.0.113.7",
+            "100.128.0.1",
+            "2001:db8::1",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(!is_private(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn pinned_addresses_are_used_and_public_answers_refused_when_private_is_expected() {
+        let pinned: IpAddr = "10.0.0.5".parse().unwrap();
+        let resolver = Resolver::new(vec![pinned], Duration::ZERO, true, false).unwrap();
+        assert_eq!(resolver.resolve("app.example").await.unwrap(), [pinned]);
+        assert!(Resolver::new(
+            vec!["203.0.113.7".parse().unwrap()],
+            Duration::ZERO,
+            true,
+            false
+        )
+        .is_err());
+
+        let resolver = Resolver::new(Vec::new(), Duration::ZERO, true, false).unwrap();
+        assert!(resolver.resolve("127.0.0.1").await.is_ok());
+        let refused = resolver.resolve("203.0.113.7").await.unwrap_err();
+        assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn ipv6_is_tried_first_when_preferred() {
+        let pinned = vec!["10.0.0.5".parse().unwrap(), "fd12::5".parse().unwrap()];
+        let resolver = Resolver::new(pinned, Duration::ZERO, true, true).unwrap();
+        let addrs = resolver.resolve("app.railway.internal").await.unwrap();
+        assert!(addrs[0].is_ipv6() && addrs[1].is_ipv4());
+
+        let loopback = IpAddr::from([127, 0, 0, 1]);
+        assert_eq!(source_address(loopback), Some(loopback));
+    }
+
+    #[tokio::test]
+    async fn answers_are_reused_until_the_refresh_interval_passes() {
+        let resolver = Resolver::new(Vec::new(), Duration::from_secs(60), false, false).unwrap();
+        let cached: IpAddr = "10.0.0.9".parse().unwrap();
+        resolver.0.answers.lock().insert(
+            "app.internal".to_string(),
+            Answer {
+                at: Instant::now(),
+                addrs: vec![cached],
+            },
+        );
+        assert_eq!(resolver.resolve("app.internal").await.unwrap(), [cached]);
+
+        // With no refresh interval every answer has already expired
+        let resolver = Resolver::new(Vec::new(), Duration::ZERO, false, false).unwrap();
+        resolver.0.answers.lock().insert(
+            "127.0.0.1".to_string(),
+            Answer {
+                at: Instant::now(),
+                addrs: vec![cached],
+            },
+        );
+        let fresh = resolver.resolve("127.0.0.1").await.unwrap();
+        assert_eq!(fresh, [IpAddr::from([127, 0, 0, 1])]);
+    }
+}
@@ -0,0 +1,132 @@
+//! When the server's periodic jobs run: content pulls, snapshot checks, health checks, status
+//! polling, circuit warm-ups and log pruning each wait on a [`Schedule`] instead of hand-rolling
+//! their own timer, wake-up and shutdown handling.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::{BoxStream, SelectAll};
+use futures::StreamExt;
+use tokio::sync::{watch, Notify};
+use tokio::time::sleep;
+
+use crate::signals::ShutdownSignal;
+
+/// An interval, plus events that bring the next run forward.
+///
+/// The interval is measured from the end of one run to the start of the next, so a slow job
+/// never has runs queue up behind it.
+pub struct Schedule {
+    /// Time between runs; zero runs only when woken
+    interval: Duration,
+    /// Run without waiting the first time
+    immediately: bool,
+    wakes: SelectAll<BoxStream<'static, ()>>,
+}
+
+impl Schedule {
+    /// Runs every `interval`, or only when woken if it is zero.
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            interval,
+            immediately: false,
+            wakes: SelectAll::new(),
+        }
+    }
+
+    /// Also runs as soon as the schedule is first waited on.
+    pub fn starting_now(mut self) -> Self {
+        self.immediately = true;
+        self
+    }
+
+    /// Also runs whenever `notify` is notified, including once for a notification that came
+    /// during the previous run.
+    pub fn or_when_notified(mut self, notify: Arc<Notify>) -> Self {
+        let wakes = futures::stream::unfold(notify, |notify| async move {
+            notify.notified().await;
+            Some(((), notify))
+        });
+        self.wakes.push(wakes.boxed());
+        self
+    }
+
+    /// Also runs whenever the value behind `values` changes, until its sender is dropped.
+    pub fn or_when_changed<T: Send + Sync + 'static>(mut self, values: watch::Receiver<T>) -> Self {
+        let wakes = futures::stream::unfold(values, |mut values| async move {
+            values.changed().await.ok()?;
+            Some(((), values))
+        });
+        self.wakes.push(wakes.boxed());
+        self
+    }
+
+    /// Waits for the next run, returning false instead once shutdown is requested.
+    pub async fn tick(&mut self, shutdown: &mut ShutdownSignal) -> bool {
+        if std::mem::take(&mut self.immediately) {
+            return true;
+        }
+        let interval = self.interval;
+        let elapsed = async {
+            if interval.is_zero() {
+                std::future::pending::<()>().await;
+            }
+            sleep(interval).await;
+        };
+        let wakes = &mut self.wakes;
+        let woken = async {
+            // An empty set of wake-ups ends at once rather than never
+            if wakes.next().await.is_none() {
+                std::future::pending::<()>().await;
+            }
+        };
+        tokio::select! {
+            _ = elapsed => true,
+            _ = woken => true,
+            _ = shutdown.recv() => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signals::{Shutdown, ShutdownReason};
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_on_the_interval_and_when_woken_until_shutdown() {
+        let shutdown = Shutdown::new();
+        let mut signal = shutdown.subscribe();
+        let notify = Arc::new(Notify::new());
+        let mut schedule = Schedule::every(Duration::from_secs(60))
+            .starting_now()
+            .or_when_notified(notify.clone());
+
+        let started = tokio::time::Instant::now();
+        assert!(schedule.tick(&mut signal).await);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert!(schedule.tick(&mut signal).await);
+        assert_eq!(started.elapsed(), Duration::from_secs(60));
+
+        // Notified while the job was running
+        notify.notify_one();
+        assert!(schedule.tick(&mut signal).await);
+        assert_eq!(started.elapsed(), Duration::from_secs(60));
+
+        shutdown.trigger(ShutdownReason::ArtiExhausted);
+        assert!(!schedule.tick(&mut signal).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_zero_interval_runs_only_on_changes() {
+        let shutdown = Shutdown::new();
+        let mut signal = shutdown.subscribe();
+        let (tx, rx) = watch::channel(0);
+        let mut schedule = Schedule::every(Duration::ZERO).or_when_changed(rx);
+
+        tx.send_replace(1);
+        assert!(schedule.tick(&mut signal).await);
+        let waiting = tokio::time::timeout(Duration::from_secs(3600), schedule.tick(&mut signal));
+        assert!(waiting.await.is_err());
+    }
+}
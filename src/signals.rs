@@ -0,0 +1,454 @@
+//! Coordinated shutdown, escalated by repeated termination signals.
+
+use std::sync::{Arc, OnceLock};
+
+use tokio::signal;
+use tokio::sync::watch;
+use tokio::time::{sleep_until, Duration, Instant};
+use tracing::{error, info, Instrument};
+
+/// A component finishing its part of the shutdown sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownEvent {
+    /// A listener stopped accepting and finished draining in-flight connections
+    ListenerDrained(&'static str),
+    /// The arti child process exited after being told to stop
+    ArtiExited,
+    /// Every component has stopped
+    Complete,
+}
+
+impl std::fmt::Display for ShutdownEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShutdownEvent::ListenerDrained(name) => write!(f, "{name} listener drained"),
+            ShutdownEvent::ArtiExited => write!(f, "arti exited"),
+            ShutdownEvent::Complete => write!(f, "shutdown complete"),
+        }
+    }
+}
+
+/// Records how long each component takes to stop once shutdown has been requested.
+///
+/// The timings are logged as they happen so operators can size Railway's stop grace period
+/// (`drainingSeconds`) from real data.
+#[derive(Debug, Default)]
+pub struct ShutdownTimings {
+    started: OnceLock<Instant>,
+}
+
+impl ShutdownTimings {
+    /// Marks the start of shutdown; only the first call has any effect.
+    pub fn begin(&self) {
+        self.started.get_or_init(Instant::now);
+    }
+
+    /// Records a component finishing shutdown, returning the time elapsed since shutdown began.
+    pub fn record(&self, event: ShutdownEvent) -> Duration {
+        let elapsed = self
+            .started
+            .get()
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        info!(elapsed_ms = elapsed.as_millis() as u64, "{event}");
+        elapsed
+    }
+}
+
+/// How far shutdown has progressed; later phases imply the earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ShutdownPhase {
+    Running,
+    /// Stop accepting work and let open connections finish
+    Graceful,
+    /// Stop waiting on open connections
+    Forced,
+}
+
+/// Why shutdown was requested, recorded for the final log line, crash dumps, and metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// SIGINT, e.g. Ctrl+C
+    Interrupt,
+    /// SIGTERM, e.g. from the platform stopping the deployment
+    Terminate,
+    /// arti kept failing and the supervisor gave up relaunching it
+    ArtiExhausted,
+    /// arti exited while running in the foreground, which is never relaunched
+    ArtiExited,
+    /// A listener failed at runtime
+    ListenerFailed,
+    /// Another process modified arti's files
+    ExternalChange,
+    /// Startup failed after arti had been launched
+    StartupFailed,
+}
+
+impl ShutdownReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShutdownReason::Interrupt => "sigint",
+            ShutdownReason::Terminate => "sigterm",
+            ShutdownReason::ArtiExhausted => "arti_exhausted",
+            ShutdownReason::ArtiExited => "arti_exited",
+            ShutdownReason::ListenerFailed => "listener_failed",
+            ShutdownReason::ExternalChange => "external_change",
+            ShutdownReason::StartupFailed => "startup_failed",
+        }
+    }
+}
+
+/// Cloneable handle used to request shutdown and to hand out [`ShutdownSignal`]s.
+///
+/// Backed by a `watch` channel so the request is latched: a subscriber created after shutdown
+/// was requested, or one that was busy when it happened, still observes it. A bounded broadcast
+/// channel can instead report the signal as lagged or drop it once more tasks subscribe.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    tx: watch::Sender<ShutdownPhase>,
+    /// When shutdown was first requested
+    requested: Arc<OnceLock<Instant>>,
+    /// Why shutdown was first requested
+    reason: Arc<OnceLock<ShutdownReason>>,
+    /// How long a requested shutdown may take before it is forced
+    grace: Option<Duration>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            tx: watch::Sender::new(ShutdownPhase::Running),
+            requested: Arc::default(),
+            reason: Arc::default(),
+            grace: None,
+        }
+    }
+
+    /// Forces shutdown once `grace` has passed since it was requested, instead of only when
+    /// [`Shutdown::force`] is called.
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = Some(grace);
+        self
+    }
+
+    /// Requests shutdown for `reason`, returning `false` if it had already been requested.
+    ///
+    /// Only the first reason is kept; later requests are usually a consequence of the first.
+    pub fn trigger(&self, reason: ShutdownReason) -> bool {
+        if self.reason.set(reason).is_ok() {
+            metrics::counter!(crate::telemetry::SHUTDOWNS.name, "reason" => reason.name())
+                .increment(1);
+        }
+        self.advance(ShutdownPhase::Graceful)
+    }
+
+    /// Why shutdown was requested, if it has been.
+    pub fn reason(&self) -> Option<ShutdownReason> {
+        self.reason.get().copied()
+    }
+
+    /// Requests that shutdown stop waiting on open connections, returning `false` if it
+    /// had already been forced.
+    pub fn force(&self) -> bool {
+        self.advance(ShutdownPhase::Forced)
+    }
+
+    fn advance(&self, phase: ShutdownPhase) -> bool {
+        // Recorded before subscribers are woken so they never see a request without its time
+        self.requested.get_or_init(Instant::now);
+        self.tx.send_if_modified(|current| {
+            let advanced = *current < phase;
+            *current = (*current).max(phase);
+            advanced
+        })
+    }
+
+    pub fn subscribe(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            rx: self.tx.subscribe(),
+            requested: self.requested.clone(),
+            grace: self.grace,
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Receiving half of [`Shutdown`], owned by a single subsystem.
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal {
+    rx: watch::Receiver<ShutdownPhase>,
+    requested: Arc<OnceLock<Instant>>,
+    grace: Option<Duration>,
+}
+
+impl ShutdownSignal {
+    /// Completes once shutdown has been requested, immediately if it already was.
+    pub async fn recv(&mut self) {
+        self.wait_for(ShutdownPhase::Graceful).await;
+    }
+
+    /// Completes once shutdown has been forced or has outlasted its grace period, immediately
+    /// if either already happened.
+    pub async fn recv_forced(&mut self) {
+        let mut requested = self.clone();
+        let deadline = async move {
+            let Some(grace) = requested.grace else {
+                return std::future::pending().await;
+            };
+            requested.recv().await;
+            let since = *requested
+                .requested
+                .get()
+                .expect("set when shutdown is requested");
+            sleep_until(since + grace).await;
+        };
+        tokio::select! {
+            _ = self.wait_for(ShutdownPhase::Forced) => {}
+            _ = deadline => {}
+        }
+    }
+
+    async fn wait_for(&mut self, phase: ShutdownPhase) {
+        // The sender lives inside every `Shutdown` handle; if they are all gone nothing can
+        // request shutdown anymore, so treat it as requested rather than waiting forever.
+        let _ = self.rx.wait_for(|current| *current >= phase).await;
+    }
+}
+
+/// Exit code used when a third signal aborts shutdown, following the shell convention for
+/// a process interrupted by SIGINT.
+const SIGNAL_ABORT_EXIT_CODE: i32 = 130;
+
+/// What a received termination signal asks of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignalAction {
+    /// Drain connections and stop arti
+    Graceful,
+    /// Stop waiting on open connections
+    Fast,
+    /// Exit immediately with [`SIGNAL_ABORT_EXIT_CODE`]
+    Abort,
+}
+
+/// Escalates shutdown with every termination signal received, so an operator stuck behind a
+/// slow drain can press Ctrl+C again instead of reaching for `kill -9`.
+#[derive(Debug, Default)]
+struct SignalDispatcher {
+    received: usize,
+}
+
+impl SignalDispatcher {
+    /// Records a signal and advances `shutdown` accordingly; exiting on
+    /// [`SignalAction::Abort`] is left to the caller.
+    fn dispatch(&mut self, shutdown: &Shutdown, reason: ShutdownReason) -> SignalAction {
+        self.received += 1;
+        match self.received {
+            1 => {
+                shutdown.trigger(reason);
+                SignalAction::Graceful
+            }
+            2 => {
+                shutdown.force();
+                SignalAction::Fast
+            }
+            _ => SignalAction::Abort,
+        }
+    }
+}
+
+pub fn install_signal_forwarders(shutdown: Shutdown) {
+    let forwarder = async move {
+        #[cfg(unix)]
+        let (mut interrupt, mut terminate) = {
+            use signal::unix::{signal, SignalKind};
+            (
+                signal(SignalKind::interrupt()).expect("failed to install Ctrl+C handler"),
+                signal(SignalKind::terminate()).expect("failed to install signal handler"),
+            )
+        };
+
+        let mut dispatcher = SignalDispatcher::default();
+        loop {
+            #[cfg(unix)]
+            let (name, reason) = tokio::select! {
+                _ = interrupt.recv() => ("Ctrl+C", ShutdownReason::Interrupt),
+                _ = terminate.recv() => ("SIGTERM", ShutdownReason::Terminate),
+            };
+
+            #[cfg(not(unix))]
+            let (name, reason) = {
+                signal::ctrl_c()
+                    .await
+                    .expect("failed to install Ctrl+C handler");
+                ("Ctrl+C", ShutdownReason::Interrupt)
+            };
+
+            match dispatcher.dispatch(&shutdown, reason) {
+                SignalAction::Graceful => {
+                    info!(signal = name, "shutting down gracefully (repeat to force)")
+                }
+                SignalAction::Fast => {
+                    info!(
+                        signal = name,
+                        "received again, closing open connections (repeat to abort)"
+                    )
+                }
+                SignalAction::Abort => {
+                    error!(signal = name, "received a third time, aborting");
+                    std::process::exit(SIGNAL_ABORT_EXIT_CODE);
+                }
+            }
+        }
+    };
+    tokio::spawn(forwarder.in_current_span());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Spawns a subsystem stand-in that counts how many times it observed shutdown.
+    fn observer(mut signal: ShutdownSignal, seen: Arc<AtomicUsize>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            signal.recv().await;
+            seen.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    #[tokio::test]
+    async fn every_subscriber_observes_shutdown_exactly_once() {
+        let shutdown = Shutdown::new();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..16)
+            .map(|_| observer(shutdown.subscribe(), seen.clone()))
+            .collect();
+
+        assert!(shutdown.trigger(ShutdownReason::ArtiExhausted));
+        // Repeated requests (e.g. a signal arriving while arti is exhausted) are not re-delivered
+        assert!(!shutdown.trigger(ShutdownReason::Terminate));
+        assert_eq!(shutdown.reason(), Some(ShutdownReason::ArtiExhausted));
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(seen.load(Ordering::SeqCst), 16);
+    }
+
+    #[tokio::test]
+    async fn late_subscriber_observes_shutdown() {
+        let shutdown = Shutdown::new();
+        shutdown.trigger(ShutdownReason::Terminate);
+
+        let seen = Arc::new(AtomicUsize::new(0));
+        observer(shutdown.subscribe(), seen.clone()).await.unwrap();
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn busy_subscriber_observes_shutdown_once_it_polls() {
+        let shutdown = Shutdown::new();
+        let mut signal = shutdown.subscribe();
+
+        // Many requests while the subscriber is not polling must not lag it out
+        for _ in 0..8 {
+            shutdown.trigger(ShutdownReason::Terminate);
+        }
+
+        tokio::time::timeout(Duration::from_secs(1), signal.recv())
+            .await
+            .expect("shutdown should be observed");
+    }
+
+    #[tokio::test]
+    async fn dropped_handles_release_subscribers() {
+        let shutdown = Shutdown::new();
+        let mut signal = shutdown.subscribe();
+        drop(shutdown);
+
+        tokio::time::timeout(Duration::from_secs(1), signal.recv())
+            .await
+            .expect("subscriber should not wait forever");
+    }
+
+    #[tokio::test]
+    async fn repeated_signals_escalate_shutdown() {
+        let shutdown = Shutdown::new();
+        let mut dispatcher = SignalDispatcher::default();
+
+        assert_eq!(
+            dispatcher.dispatch(&shutdown, ShutdownReason::Interrupt),
+            SignalAction::Graceful
+        );
+        assert_eq!(*shutdown.tx.borrow(), ShutdownPhase::Graceful);
+
+        assert_eq!(
+            dispatcher.dispatch(&shutdown, ShutdownReason::Interrupt),
+            SignalAction::Fast
+        );
+        assert_eq!(*shutdown.tx.borrow(), ShutdownPhase::Forced);
+
+        assert_eq!(
+            dispatcher.dispatch(&shutdown, ShutdownReason::Interrupt),
+            SignalAction::Abort
+        );
+        assert_eq!(
+            dispatcher.dispatch(&shutdown, ShutdownReason::Interrupt),
+            SignalAction::Abort
+        );
+    }
+
+    #[tokio::test]
+    async fn first_signal_does_not_force_shutdown() {
+        let shutdown = Shutdown::new();
+        let mut signal = shutdown.subscribe();
+        SignalDispatcher::default().dispatch(&shutdown, ShutdownReason::Interrupt);
+
+        signal.recv().await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), signal.recv_forced())
+                .await
+                .is_err(),
+            "a single signal should leave connections draining"
+        );
+    }
+
+    #[tokio::test]
+    async fn forced_shutdown_releases_every_waiter() {
+        let shutdown = Shutdown::new();
+        let mut graceful = shutdown.subscribe();
+        let mut forced = shutdown.subscribe();
+
+        // Forcing without a prior graceful request still counts as a shutdown request
+        assert!(shutdown.force());
+        assert!(!shutdown.force());
+        assert!(!shutdown.trigger(ShutdownReason::Terminate));
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            graceful.recv().await;
+            forced.recv_forced().await;
+        })
+        .await
+        .expect("forced shutdown should be observed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn grace_period_forces_shutdown() {
+        let shutdown = Shutdown::new().with_grace(Duration::from_secs(10));
+        let mut signal = shutdown.subscribe();
+
+        // The grace period only runs once shutdown has been requested
+        sleep_until(Instant::now() + Duration::from_secs(30)).await;
+        let requested = Instant::now();
+        shutdown.trigger(ShutdownReason::Terminate);
+
+        signal.recv_forced().await;
+        assert_eq!(requested.elapsed(), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,448 @@
+//! Static sites published as archives: a tarball or zip file, on disk or at a URL, unpacked into
+//! a directory named after its digest and swapped in whole once it is complete.
+//!
+//! Requests in flight keep the directory they started with, and every later one sees the new
+//! site, so a CI artifact can update an onion mirror without a moment of half-unpacked files.
+
+use std::io::{self, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+
+/// Largest archive fetched or read.
+const MAX_ARCHIVE_BYTES: usize = 512 * 1024 * 1024;
+/// Most an archive may unpack to, so a small zip or gzip bomb can't fill the volume.
+const LIMITS: Limits = Limits {
+    bytes: 2 * 1024 * 1024 * 1024,
+    entries: 100_000,
+};
+/// How long downloading an archive may take.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(300);
+/// Prefix of directories still being unpacked, which are never served.
+const UNPACKING_PREFIX: &str = ".unpacking-";
+
+/// Bounds on what unpacking an archive may write.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// Bytes of file contents
+    bytes: u64,
+    /// Files and directories
+    entries: usize,
+}
+
+/// Where the archive comes from.
+enum Source {
+    File(PathBuf),
+    Url(Uri),
+}
+
+/// The site version being served.
+struct Version {
+    digest: String,
+    root: Arc<Path>,
+}
+
+/// A static site served from the latest unpacked version of an archive.
+pub struct StaticSnapshot {
+    source: Source,
+    /// Holds one subdirectory per unpacked version
+    dir: PathBuf,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    current: RwLock<Option<Version>>,
+    /// Wakes the refresh loop ahead of its interval
+    pub refresh: Arc<tokio::sync::Notify>,
+}
+
+impl StaticSnapshot {
+    /// `source` is an `http://` or `https://` URL, or else a path to the archive.
+    pub fn new(source: &str, dir: PathBuf) -> Result<Self, String> {
+        let source = match source.split_once("://") {
+            Some(("http" | "https", _)) => Source::Url(
+                source
+                    .parse()
+                    .map_err(|e| format!("invalid snapshot URL {source:?}: {e}"))?,
+            ),
+            Some(_) => {
+                return Err(format!(
+                    "snapshot URL {source:?} must be http:// or https://"
+                ))
+            }
+            None => Source::File(PathBuf::from(source)),
+        };
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            source,
+            dir,
+            client: Client::builder(TokioExecutor::new()).build(connector),
+            current: RwLock::new(None),
+            refresh: Arc::default(),
+        })
+    }
+
+    /// Where the archive is read from, for log lines.
+    pub fn source(&self) -> String {
+        match &self.source {
+            Source::File(path) => path.display().to_string(),
+            Source::Url(uri) => uri.to_string(),
+        }
+    }
+
+    /// Root of the version being served, if one has been unpacked yet.
+    pub fn root(&self) -> Option<Arc<Path>> {
+        self.current
+            .read()
+            .as_ref()
+            .map(|version| version.root.clone())
+    }
+
+    /// Reads the archive and, if it changed, unpacks it and swaps it in, removing all but the
+    /// previous version.
+    ///
+    /// Returns the digest of the version now being served, and whether it is a new one.
+    pub async fn update(&self) -> Result<(String, bool), String> {
+        let archive = self.fetch().await?;
+        let digest: String = Sha256::digest(&archive)[..8]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let previous = self.current.read().as_ref().map(|v| v.digest.clone());
+        if previous.as_deref() == Some(digest.as_str()) {
+            return Ok((digest, false));
+        }
+
+        let (dir, version) = (self.dir.clone(), digest.clone());
+        let root = tokio::task::spawn_blocking(move || unpack_version(&dir, &version, &archive))
+            .await
+            .map_err(|e| format!("unpacking failed: {e}"))?
+            .map_err(|e| format!("unable to unpack the snapshot: {e}"))?;
+        *self.current.write() = Some(Version {
+            digest: digest.clone(),
+            root: Arc::from(root),
+        });
+
+        // Requests still reading the previous version keep it; anything older can go
+        let keep = [Some(digest.as_str()), previous.as_deref()];
+        if let Ok(entries) = std::fs::read_dir(&self.dir) {
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                if !keep.contains(&name.to_str()) {
+                    let _ = std::fs::remove_dir_all(entry.path());
+                }
+            }
+        }
+        Ok((digest, true))
+    }
+
+    async fn fetch(&self) -> Result<Vec<u8>, String> {
+        let uri = match &self.source {
+            Source::File(path) => {
+                let archive = tokio::fs::read(path)
+                    .await
+                    .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+                if archive.len() > MAX_ARCHIVE_BYTES {
+                    return Err(format!("{} is too large", path.display()));
+                }
+                return Ok(archive);
+            }
+            Source::Url(uri) => uri,
+        };
+        let request = Request::get(uri.clone())
+            .header(header::USER_AGENT, env!("CARGO_PKG_NAME"))
+            .body(Body::empty())
+            .expect("valid request");
+        let fetch = async {
+            let response = self
+                .client
+                .request(request)
+                .await
+                .map_err(|e| format!("unable to fetch {uri}: {e}"))?;
+            if response.status() != StatusCode::OK {
+                return Err(format!("fetching {uri} returned {}", response.status()));
+            }
+            axum::body::to_bytes(Body::new(response.into_body()), MAX_ARCHIVE_BYTES)
+                .await
+                .map(Vec::from)
+                .map_err(|e| format!("unable to download {uri}: {e}"))
+        };
+        tokio::time::timeout(FETCH_TIMEOUT, fetch)
+            .await
+            .map_err(|_| format!("fetching {uri} timed out"))?
+    }
+}
+
+/// Unpacks `archive` into `dir/version` unless an earlier run already did, returning the
+/// directory to serve.
+///
+/// The files are written to a scratch directory renamed into place once complete, so a version
+/// directory is never seen half-written, even after a crash.
+fn unpack_version(dir: &Path, version: &str, archive: &[u8]) -> io::Result<PathBuf> {
+    let target = dir.join(version);
+    if !target.is_dir() {
+        let scratch = dir.join(format!("{UNPACKING_PREFIX}{version}"));
+        let _ = std::fs::remove_dir_all(&scratch);
+        std::fs::create_dir_all(&scratch)?;
+        let result =
+            unpack(archive, &scratch, LIMITS).and_then(|()| std::fs::rename(&scratch, &target));
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&scratch);
+        }
+        result?;
+    }
+    Ok(site_root(target))
+}
+
+/// The directory to serve: an archive holding nothing but one directory is served from inside
+/// it, as CI artifacts often wrap the site in one.
+fn site_root(target: PathBuf) -> PathBuf {
+    let entries: Vec<_> = match std::fs::read_dir(&target) {
+        Ok(entries) => entries.flatten().collect(),
+        Err(_) => return target,
+    };
+    match entries.as_slice() {
+        [only] if only.path().is_dir() => only.path(),
+        _ => target,
+    }
+}
+
+/// Writes the regular files and directories in a gzipped or plain tarball or a zip file under
+/// `dest`.
+///
+/// Links and special files are refused rather than skipped, as are paths leading outside `dest`
+/// and archives that are empty or unpack to more than `limits`, so nothing in the archive can
+/// make the server read files it wasn't given or fill the disk.
+fn unpack(archive: &[u8], dest: &Path, limits: Limits) -> io::Result<()> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let mut unpacked = Limits {
+        bytes: 0,
+        entries: 0,
+    };
+    let mut count = |bytes: u64| {
+        unpacked.bytes += bytes;
+        unpacked.entries += 1;
+        match unpacked.bytes > limits.bytes || unpacked.entries > limits.entries {
+            true => Err(invalid(format!(
+                "unpacks to more than {} bytes or {} files",
+                limits.bytes, limits.entries
+            ))),
+            false => Ok(()),
+        }
+    };
+    if archive.starts_with(b"PK\x03\x04") || archive.starts_with(b"PK\x05\x06") {
+        let mut zip =
+            zip::ZipArchive::new(Cursor::new(archive)).map_err(|e| invalid(e.to_string()))?;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i).map_err(|e| invalid(e.to_string()))?;
+            let name = file.name().to_string();
+            let path = file
+                .enclosed_name()
+                .filter(|path| is_relative(path))
+                .ok_or_else(|| invalid(format!("{name:?} leads outside the archive")))?;
+            if file.is_symlink() {
+                return Err(invalid(format!("{name:?} is a link")));
+            }
+            let size = file.size();
+            count(size)?;
+            let path = dest.join(path);
+            if file.is_dir() {
+                std::fs::create_dir_all(&path)?;
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // Counted by the size the archive declares, so more than that is refused
+            let written = io::copy(
+                &mut (&mut file).take(size + 1),
+                &mut std::fs::File::create(&path)?,
+            )?;
+            if written > size {
+                return Err(invalid(format!("{name:?} is larger than it claims")));
+            }
+        }
+    } else {
+        let reader: Box<dyn Read> = match archive.starts_with(&[0x1f, 0x8b]) {
+            true => Box::new(flate2::read::GzDecoder::new(archive)),
+            false => Box::new(archive),
+        };
+        let mut tar = tar::Archive::new(reader);
+        tar.set_preserve_permissions(false);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            if !is_relative(&path) {
+                return Err(invalid(format!(
+                    "{} leads outside the archive",
+                    path.display()
+                )));
+            }
+            match entry.header().entry_type() {
+                tar::EntryType::Regular | tar::EntryType::Directory => {}
+                // Global pax records describe the entries that follow; `entries` applies local
+                // pax and GNU long-name records to their entry itself
+                kind if kind.is_pax_global_extensions() || kind.is_pax_local_extensions() => {
+                    continue
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "{} is not a file or directory",
+                        path.display()
+                    )))
+                }
+            }
+            // An entry's reader stops at the size in its header
+            count(entry.size())?;
+            if !entry.unpack_in(dest)? {
+                return Err(invalid(format!(
+                    "{} leads outside the archive",
+                    path.display()
+                )));
+            }
+        }
+    }
+    if std::fs::read_dir(dest)?.next().is_none() {
+        return Err(invalid("empty, or not a tarball or zip file".to_string()));
+    }
+    Ok(())
+}
+
+/// Whether `path` stays below the directory it is joined to.
+fn is_relative(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tarball(entries: &[(&str, tar::EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for &(path, kind, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            if kind == tar::EntryType::Symlink {
+                header.set_link_name("/etc/passwd").unwrap();
+            }
+            // set_path refuses `..`, which is exactly what has to be tested
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_cksum();
+            builder.append(&header, contents).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        io::Write::write_all(&mut gzip, &tar).unwrap();
+        gzip.finish().unwrap()
+    }
+
+    fn zip_file(path: &str, contents: &[u8]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(path, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        io::Write::write_all(&mut zip, contents).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_swapped_in_whole_and_old_ones_removed() {
+        let dir = std::env::temp_dir().join(format!("snapshot-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("site.tar.gz");
+        let snapshot =
+            StaticSnapshot::new(archive.to_str().unwrap(), dir.join("versions")).unwrap();
+        let regular = tar::EntryType::Regular;
+
+        let mut versions = Vec::new();
+        for contents in [&b"one"[..], b"two", b"three"] {
+            let site = tarball(&[("site/index.html", regular, contents)]);
+            std::fs::write(&archive, site).unwrap();
+            let (digest, changed) = snapshot.update().await.unwrap();
+            assert!(changed);
+            let root = snapshot.root().unwrap();
+            // Served from inside the archive's only directory
+            assert_eq!(std::fs::read(root.join("index.html")).unwrap(), contents);
+            versions.push(digest);
+        }
+        assert!(!snapshot.update().await.unwrap().1);
+        // The one before last stays for requests still reading it
+        let mut left: Vec<_> = std::fs::read_dir(dir.join("versions"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        let mut expected = versions[1..].to_vec();
+        expected.sort();
+        assert_eq!(left, expected);
+
+        std::fs::write(&archive, zip_file("index.html", b"zipped")).unwrap();
+        snapshot.update().await.unwrap();
+        let root = snapshot.root().unwrap();
+        assert_eq!(std::fs::read(root.join("index.html")).unwrap(), b"zipped");
+
+        // A bad archive leaves the current version in place
+        for bad in [
+            tarball(&[("../escape.html", regular, b"x")]),
+            tarball(&[("link.html", tar::EntryType::Symlink, b"")]),
+            zip_file("../escape.html", b"x"),
+            b"not an archive".to_vec(),
+        ] {
+            std::fs::write(&archive, bad).unwrap();
+            assert!(snapshot.update().await.is_err());
+            assert_eq!(snapshot.root().unwrap(), root);
+        }
+        assert!(!dir.join("escape.html").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unpacking_is_bounded_and_refuses_empty_archives() {
+        let dir = std::env::temp_dir().join(format!("snapshot-limits-{}", std::process::id()));
+        let unpack_into = |name: &str, archive: &[u8], bytes: u64, entries: usize| {
+            let dest = dir.join(name);
+            std::fs::create_dir_all(&dest).unwrap();
+            unpack(archive, &dest, Limits { bytes, entries }).map(|()| dest)
+        };
+        let regular = tar::EntryType::Regular;
+        let three = tarball(&[
+            ("a", regular, b"aaaa"),
+            ("b", regular, b"bbbb"),
+            ("c", regular, b"cccc"),
+        ]);
+        assert!(unpack_into("fits", &three, 12, 3).is_ok());
+        assert!(unpack_into("bytes", &three, 11, 3).is_err());
+        assert!(unpack_into("entries", &three, 12, 2).is_err());
+        assert!(unpack_into("zip", &zip_file("a", b"aaaa"), 3, 3).is_err());
+
+        let empty_zip = zip::ZipWriter::new(Cursor::new(Vec::new()))
+            .finish()
+            .unwrap()
+            .into_inner();
+        assert!(unpack_into("empty-zip", &empty_zip, 12, 3).is_err());
+        assert!(unpack_into("empty-tar", &tarball(&[]), 12, 3).is_err());
+
+        // Names past ustar's 100 bytes are carried in a GNU long-name record
+        let long = format!("{}/index.html", "d".repeat(120));
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(2);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, &long, &b"ok"[..]).unwrap();
+        let dest = unpack_into("long", &builder.into_inner().unwrap(), 12, 3).unwrap();
+        assert_eq!(std::fs::read(dest.join(long)).unwrap(), b"ok");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! Outbound connections through arti's SOCKS port, for reaching onion services (or anything
+//! else) over Tor from the same process that serves one.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::Uri;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Opens a stream to `host:port` through the SOCKS5 proxy at `proxy`.
+///
+/// The host name is handed to the proxy unresolved, as arti requires for onion addresses and
+/// so that lookups of other names happen over Tor too.
+pub async fn connect(proxy: SocketAddr, host: &str, port: u16) -> io::Result<TcpStream> {
+    let protocol = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut stream = TcpStream::connect(proxy).await?;
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut greeting = [0; 2];
+    stream.read_exact(&mut greeting).await?;
+    if greeting != [5, 0] {
+        return Err(protocol(format!("unexpected SOCKS reply {greeting:?}")));
+    }
+    let length = u8::try_from(host.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "host too long for SOCKS"))?;
+    let mut request = vec![5, 1, 0, 3, length];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "SOCKS connect to {host}:{port} failed with reply {}",
+                reply[1]
+            ),
+        ));
+    }
+    // The address the proxy bound, which is of no use to us
+    let bound = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => usize::from(stream.read_u8().await?),
+        atyp => return Err(protocol(format!("unknown SOCKS address type {atyp}"))),
+    };
+    let mut skipped = vec![0; bound + 2];
+    stream.read_exact(&mut skipped).await?;
+    Ok(stream)
+}
+
+/// A [`hyper_util`] connector that makes every connection through a SOCKS5 proxy.
+#[derive(Debug, Clone, Copy)]
+pub struct SocksConnector {
+    proxy: SocketAddr,
+}
+
+impl SocksConnector {
+    pub fn new(proxy: SocketAddr) -> Self {
+        Self { proxy }
+    }
+}
+
+impl tower_service::Service<Uri> for SocksConnector {
+    type Response = TokioIo<TcpStream>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let proxy = self.proxy;
+        Box::pin(async move {
+            let invalid =
+                || io::Error::new(io::ErrorKind::InvalidInput, format!("no host in {uri}"));
+            let host = uri.host().ok_or_else(invalid)?;
+            // Brackets are URI syntax, not part of the address
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                Some("https") => 443,
+                _ => 80,
+            });
+            connect(proxy, host, port).await.map(TokioIo::new)
+        })
+    }
+}
+
+/// HTTP client whose requests go through a SOCKS5 proxy; https:// URLs are verified against the
+/// bundled web roots.
+pub type HttpClient = Client<HttpsConnector<SocksConnector>, Body>;
+
+/// Builds an [`HttpClient`] for arti's SOCKS port at `proxy`.
+///
+/// Onion services are usually plain `http://`: the onion address already authenticates the
+/// service and encrypts the connection.
+pub fn http_client(proxy: SocketAddr) -> HttpClient {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(SocksConnector::new(proxy));
+    Client::builder(TokioExecutor::new()).build(connector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn the_http_client_hands_onion_hosts_to_the_proxy_unresolved() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = http_client(listener.local_addr().unwrap());
+        let proxy = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[5, 0]).await.unwrap();
+            let mut connect = [0; 5];
+            stream.read_exact(&mut connect).await.unwrap();
+            let mut destination = vec![0; usize::from(connect[4]) + 2];
+            stream.read_exact(&mut destination).await.unwrap();
+            stream
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            let mut request = vec![0; 1024];
+            let read = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nhi")
+                .await
+                .unwrap();
+            (
+                destination,
+                String::from_utf8_lossy(&request[..read]).into_owned(),
+            )
+        });
+
+        let uri = "http://example.onion:8080/api".parse().unwrap();
+        let response = client.get(uri).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(Body::new(response.into_body()), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body, "hi");
+        let (destination, request) = proxy.await.unwrap();
+        assert_eq!(destination, b"example.onion\x1f\x90");
+        assert!(request.starts_with("GET /api HTTP/1.1\r\n"), "{request}");
+    }
+}
@@ -0,0 +1,1658 @@
+//! Keeps an arti process running, relaunching it with backoff when it fails.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::RwLock;
+use serde::Serialize;
+use tokio::process::Command;
+use tokio::sync::{watch, Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, error, info, warn, Instrument};
+
+use crate::signals::{Shutdown, ShutdownEvent, ShutdownReason, ShutdownSignal, ShutdownTimings};
+use crate::{telemetry, Diagnostics, LogThrottle};
+
+/// Location of the arti binary and the configuration it should be launched with.
+///
+/// Shared by the supervisor and the discovery task so both talk to the same arti installation.
+#[derive(Debug, Clone)]
+pub struct Arti {
+    pub binary: PathBuf,
+    pub config: PathBuf,
+    /// Working directory arti is launched in
+    pub dir: PathBuf,
+    /// arti's state directory, holding the keystore
+    pub state_dir: PathBuf,
+    /// Collects the tail of the proxy's output for crash dumps
+    pub diagnostics: Arc<Diagnostics>,
+    /// Updated from arti's log as it bootstraps
+    pub bootstrap: Arc<RwLock<BootstrapState>>,
+    /// Updated from arti's log as it uploads the onion service descriptor
+    pub descriptor: Arc<RwLock<DescriptorState>>,
+}
+
+/// Caps how many child processes run at once: arti itself, discovery's `arti hss` queries, and
+/// git.
+///
+/// Callers wait for a slot rather than fail, so restarts and polls that pile up during an
+/// outage are spread out instead of all spawning at once. arti holds its slot for as long as it
+/// runs.
+#[derive(Debug, Clone)]
+pub struct ProcessBudget {
+    slots: Arc<Semaphore>,
+}
+
+impl ProcessBudget {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(slots)),
+        }
+    }
+
+    /// Waits for a free slot, held until the returned permit is dropped.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        if let Ok(slot) = self.slots.clone().try_acquire_owned() {
+            return slot;
+        }
+        debug!("waiting for a free child process slot");
+        self.slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the budget is never closed")
+    }
+}
+
+/// What the arti supervisor is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtiStatus {
+    /// arti has not been launched yet
+    Starting,
+    /// The arti child process is running
+    Running,
+    /// arti is down and will be relaunched at the given instant
+    Backoff { until: Instant },
+    /// The restart limit was hit, or arti exited in the foreground; it will not be relaunched
+    Exhausted,
+}
+
+impl ArtiStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArtiStatus::Starting => "starting",
+            ArtiStatus::Running => "running",
+            ArtiStatus::Backoff { .. } => "backoff",
+            ArtiStatus::Exhausted => "exhausted",
+        }
+    }
+}
+
+/// Failures within [`ARTI_FAILURE_WINDOW`] after which arti is no longer relaunched.
+const ARTI_MAX_FAILURES: usize = 5;
+/// Rolling window in which failures count towards [`ARTI_MAX_FAILURES`].
+const ARTI_FAILURE_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// A run at least this long forgives every earlier failure.
+const ARTI_STABLE_RUN: Duration = Duration::from_secs(5 * 60);
+/// Backoff after the first failure, doubled for every further failure in the window.
+const ARTI_BACKOFF_BASE: Duration = Duration::from_secs(3);
+/// Upper bound of the backoff before jitter.
+const ARTI_BACKOFF_MAX: Duration = Duration::from_secs(2 * 60);
+
+/// How many failures arti is allowed, and how long to wait between relaunches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartLimits {
+    /// Failures within `failure_window` after which arti is no longer relaunched
+    pub max_failures: usize,
+    pub failure_window: Duration,
+    /// Backoff after the first failure, doubled for every further failure in the window
+    pub backoff_base: Duration,
+    /// Upper bound of the backoff before jitter
+    pub backoff_max: Duration,
+}
+
+impl Default for RestartLimits {
+    fn default() -> Self {
+        Self {
+            max_failures: ARTI_MAX_FAILURES,
+            failure_window: ARTI_FAILURE_WINDOW,
+            backoff_base: ARTI_BACKOFF_BASE,
+            backoff_max: ARTI_BACKOFF_MAX,
+        }
+    }
+}
+
+/// Decides whether, and after how long, arti is relaunched after a failure.
+///
+/// Failures are counted in a rolling window instead of over the process lifetime, so a bad day
+/// months into a deployment isn't held against a budget used up long ago.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    limits: RestartLimits,
+    /// When each failure within the window happened, oldest first
+    failures: VecDeque<Instant>,
+    /// Returns the fraction (0.0-1.0) of up to half of each backoff to skip
+    jitter: fn() -> f64,
+    /// Relaunches over the process lifetime, shared with the status API
+    restarts: Arc<AtomicU64>,
+    /// File the failures are mirrored to, so the budget outlives the wrapper process
+    journal: Option<PathBuf>,
+    /// The same moment on both clocks, for converting failures to and from wall-clock time
+    clock: (Instant, SystemTime),
+}
+
+/// Journal of recent arti failures in arti's state directory, as Unix milliseconds.
+///
+/// If Railway keeps restarting the wrapper itself, each new process would otherwise start with
+/// a fresh budget and relaunch arti straight away, hammering the Tor network forever.
+pub const RESTART_JOURNAL_FILE: &str = "arti-restarts.json";
+
+impl RestartPolicy {
+    pub fn new(jitter: fn() -> f64) -> Self {
+        Self {
+            limits: RestartLimits::default(),
+            failures: VecDeque::new(),
+            jitter,
+            restarts: Arc::new(AtomicU64::new(0)),
+            journal: None,
+            clock: (Instant::now(), SystemTime::now()),
+        }
+    }
+
+    /// Replaces the default limits; call before [`RestartPolicy::journaled`], which drops
+    /// recorded failures that fall outside the window.
+    pub fn with_limits(mut self, limits: RestartLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Persists failures to `path`, carrying over those an earlier run recorded there.
+    pub fn journaled(mut self, path: PathBuf) -> Self {
+        let recorded: Vec<u64> = std::fs::read(&path)
+            .ok()
+            .and_then(|journal| serde_json::from_slice(&journal).ok())
+            .unwrap_or_default();
+        self.restore(&recorded, Instant::now(), SystemTime::now());
+        if !self.failures.is_empty() {
+            warn!(
+                failures = self.failures.len(),
+                journal = %path.display(),
+                "arti failed recently in an earlier run; its restart budget carries over"
+            );
+        }
+        self.journal = Some(path);
+        self
+    }
+
+    /// Takes over `recorded` failures (Unix milliseconds) that are still inside the window.
+    fn restore(&mut self, recorded: &[u64], now: Instant, wall: SystemTime) {
+        self.clock = (now, wall);
+        let mut failures: Vec<_> = recorded
+            .iter()
+            .filter_map(|&unix_ms| {
+                let age = wall
+                    .duration_since(UNIX_EPOCH + Duration::from_millis(unix_ms))
+                    .unwrap_or_default();
+                (age < self.limits.failure_window)
+                    .then(|| now.checked_sub(age))
+                    .flatten()
+            })
+            .collect();
+        failures.sort();
+        self.failures = failures.into();
+    }
+
+    /// The failures in the window as Unix milliseconds, as written to the journal.
+    fn recorded(&self) -> Vec<u64> {
+        let (anchor, wall) = self.clock;
+        self.failures
+            .iter()
+            .map(|&failure| {
+                let at = match failure.checked_duration_since(anchor) {
+                    Some(after) => wall + after,
+                    None => wall - anchor.duration_since(failure),
+                };
+                at.duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64
+            })
+            .collect()
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.journal else {
+            return;
+        };
+        let journal = serde_json::to_vec(&self.recorded()).expect("timestamps serialize");
+        if let Err(e) = std::fs::write(path, journal) {
+            warn!(journal = %path.display(), error = %e, "unable to record arti failures");
+        }
+    }
+
+    /// When arti may first be launched, if failures carried over from an earlier run still
+    /// call for waiting.
+    ///
+    /// That is the backoff owed for the last failure, or, once the budget is spent, until enough
+    /// failures have left the window to allow another one.
+    fn resume_at(&self, now: Instant) -> Option<Instant> {
+        let last = *self.failures.back()?;
+        let until = match self.failures.len().checked_sub(self.limits.max_failures) {
+            Some(oldest) => self.failures[oldest] + self.limits.failure_window,
+            None => last + self.backoff(),
+        };
+        (until > now).then_some(until)
+    }
+
+    /// Backoff for the failures in the window, before jitter.
+    fn backoff(&self) -> Duration {
+        let exponent = self.failures.len().saturating_sub(1).min(16) as u32;
+        self.limits
+            .backoff_base
+            .saturating_mul(1 << exponent)
+            .min(self.limits.backoff_max)
+    }
+
+    /// Relaunches counted over the process lifetime, for reporting.
+    pub fn restarts(&self) -> Arc<AtomicU64> {
+        self.restarts.clone()
+    }
+
+    /// Counts a relaunch of arti.
+    fn relaunching(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+        metrics::counter!(telemetry::ARTI_RESTARTS.name).increment(1);
+    }
+
+    /// Forgets earlier failures if arti ran from `since` until `now` for long enough.
+    fn ran(&mut self, since: Instant, now: Instant) {
+        if now.saturating_duration_since(since) >= ARTI_STABLE_RUN && !self.failures.is_empty() {
+            self.failures.clear();
+            self.save();
+        }
+    }
+
+    /// Records a failure at `now`, returning the backoff before the next launch or `None` once
+    /// the window's budget is spent.
+    ///
+    /// The backoff doubles with every failure in the window. Jitter shortens it by up to half,
+    /// so replicas that crashed together don't relaunch in lockstep.
+    fn failed(&mut self, now: Instant) -> Option<Duration> {
+        let window = self.limits.failure_window;
+        while self
+            .failures
+            .front()
+            .is_some_and(|&failure| now.saturating_duration_since(failure) >= window)
+        {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(now);
+        self.save();
+        if self.failures.len() >= self.limits.max_failures {
+            return None;
+        }
+
+        let delay = self.backoff();
+        let jitter = (self.jitter)().clamp(0.0, 1.0);
+        Some(delay - delay.mul_f64(jitter / 2.0))
+    }
+}
+
+/// Probes arti's SOCKS listener while it runs, since a hung arti never exits on its own.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    pub socks: SocketAddr,
+    /// Time between probes, and the most a single probe may take
+    pub interval: Duration,
+    /// Consecutive failed probes after which arti is killed and relaunched
+    pub max_failures: u32,
+}
+
+impl Watchdog {
+    /// Opens a connection to the SOCKS listener and offers SOCKS5 without authentication,
+    /// which a responsive arti accepts without touching the Tor network.
+    async fn probe(&self) -> std::io::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(self.socks).await?;
+        stream.write_all(&[5, 1, 0]).await?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        match reply {
+            [5, 0] => Ok(()),
+            reply => Err(std::io::Error::other(format!(
+                "unexpected SOCKS reply {reply:?}"
+            ))),
+        }
+    }
+
+    /// Completes once `max_failures` probes in a row have failed; never without a watchdog.
+    async fn tripped(watchdog: Option<&Watchdog>) {
+        let Some(watchdog) = watchdog else {
+            return std::future::pending().await;
+        };
+        let mut failures = 0;
+        loop {
+            tokio::time::sleep(watchdog.interval).await;
+            match tokio::time::timeout(watchdog.interval, watchdog.probe()).await {
+                Ok(Ok(())) => failures = 0,
+                Ok(Err(e)) => {
+                    failures += 1;
+                    warn!(failures, error = %e, "arti failed a SOCKS probe");
+                }
+                Err(_) => {
+                    failures += 1;
+                    warn!(failures, "arti did not answer a SOCKS probe in time");
+                }
+            }
+            if failures >= watchdog.max_failures {
+                return;
+            }
+        }
+    }
+}
+
+/// Asks the supervisor to kill arti and launch it again, e.g. to bounce a wedged process
+/// without redeploying the whole service.
+///
+/// A request made while arti is starting takes effect once it is running.
+#[derive(Debug, Clone, Default)]
+pub struct RestartRequests {
+    notify: Arc<Notify>,
+}
+
+impl RestartRequests {
+    pub fn request(&self) {
+        self.notify.notify_one();
+    }
+
+    async fn requested(&self) {
+        self.notify.notified().await
+    }
+}
+
+/// Completes when chaos mode decides to kill the current arti process; never otherwise.
+async fn chaos_arti_kill() {
+    #[cfg(feature = "chaos")]
+    if let Some(lifetime) = crate::chaos::arti_lifetime() {
+        tokio::time::sleep(lifetime).await;
+        return;
+    }
+    std::future::pending::<()>().await
+}
+
+/// Lifecycle of the arti supervisor. `attempt` counts launches, starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SupervisorState {
+    /// Nothing has been launched yet
+    Idle,
+    /// arti is about to be launched
+    Spawning { attempt: usize },
+    /// The arti child process has been running since `since`
+    Running { attempt: usize, since: Instant },
+    /// arti is down and will be relaunched once `until` has passed
+    Backoff { attempt: usize, until: Instant },
+    /// The restart limit was hit; arti will not be relaunched
+    Exhausted,
+    /// Shutdown was requested; arti is being stopped for good
+    ShuttingDown,
+}
+
+impl std::fmt::Display for SupervisorState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SupervisorState::Idle => write!(f, "idle"),
+            SupervisorState::Spawning { attempt } => write!(f, "spawning (attempt {attempt})"),
+            SupervisorState::Running { attempt, .. } => write!(f, "running (attempt {attempt})"),
+            SupervisorState::Backoff { attempt, until } => write!(
+                f,
+                "backing off after attempt {attempt} for {}ms",
+                until.saturating_duration_since(Instant::now()).as_millis()
+            ),
+            SupervisorState::Exhausted => write!(f, "exhausted"),
+            SupervisorState::ShuttingDown => write!(f, "shutting down"),
+        }
+    }
+}
+
+/// Something that happened to the supervisor, fed to [`SupervisorState::on`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SupervisorEvent {
+    Start,
+    Spawned,
+    SpawnFailed,
+    Exited,
+    BackoffElapsed,
+    /// An operator asked for arti to be restarted
+    RestartRequested,
+    ShutdownRequested,
+}
+
+impl SupervisorState {
+    /// Returns the state following `event` at `now`, consulting `policy` on failures.
+    ///
+    /// Events that don't apply to the current state leave it unchanged, and the two terminal
+    /// states ignore everything.
+    fn on(self, event: SupervisorEvent, now: Instant, policy: &mut RestartPolicy) -> Self {
+        use SupervisorEvent::*;
+        use SupervisorState::*;
+
+        match (self, event) {
+            (Exhausted | ShuttingDown, _) => self,
+            (_, ShutdownRequested) => ShuttingDown,
+            (Idle, Start) => match policy.resume_at(now) {
+                // Honour a backoff an earlier run of the wrapper was still serving
+                Some(until) => Backoff { attempt: 0, until },
+                None => Spawning { attempt: 1 },
+            },
+            (Spawning { attempt }, Spawned) => Running {
+                attempt,
+                since: now,
+            },
+            (Spawning { attempt }, SpawnFailed) | (Running { attempt, .. }, Exited) => {
+                if let Running { since, .. } = self {
+                    policy.ran(since, now);
+                }
+                match policy.failed(now) {
+                    Some(delay) => Backoff {
+                        attempt,
+                        until: now + delay,
+                    },
+                    None => Exhausted,
+                }
+            }
+            (Backoff { attempt, until }, BackoffElapsed) if now >= until => Spawning {
+                attempt: attempt + 1,
+            },
+            // Requested restarts aren't failures, so they relaunch at once and cost no budget
+            (Running { attempt, .. } | Backoff { attempt, .. }, RestartRequested) => {
+                if let Running { since, .. } = self {
+                    policy.ran(since, now);
+                }
+                Backoff {
+                    attempt,
+                    until: now,
+                }
+            }
+            _ => self,
+        }
+    }
+
+    /// What dependents should see, or `None` to keep showing the previous status.
+    fn status(&self) -> Option<ArtiStatus> {
+        match *self {
+            SupervisorState::Idle => Some(ArtiStatus::Starting),
+            SupervisorState::Running { .. } => Some(ArtiStatus::Running),
+            SupervisorState::Backoff { until, .. } => Some(ArtiStatus::Backoff { until }),
+            SupervisorState::Exhausted => Some(ArtiStatus::Exhausted),
+            SupervisorState::Spawning { .. } | SupervisorState::ShuttingDown => None,
+        }
+    }
+}
+
+/// Launches arti processes; abstracted so the supervisor can be driven by fakes in tests.
+pub trait ArtiLauncher {
+    type Process: ArtiProcess;
+
+    fn launch(&mut self) -> std::io::Result<Self::Process>;
+}
+
+/// A running arti process.
+pub trait ArtiProcess: Send {
+    /// Waits for the process to exit on its own.
+    fn wait(&mut self) -> impl Future<Output = std::io::Result<ExitStatus>> + Send;
+
+    /// Asks the process to exit cleanly, without waiting for it to do so.
+    fn terminate(&mut self);
+
+    /// Kills the process and waits for it to exit.
+    fn kill(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+impl ArtiLauncher for Arti {
+    type Process = tokio::process::Child;
+
+    fn launch(&mut self) -> std::io::Result<Self::Process> {
+        let mut child = Command::new(&self.binary)
+            .current_dir(&self.dir)
+            .arg("proxy")
+            .arg("-c")
+            .arg(&self.config)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // A relaunched arti bootstraps from scratch
+        *self.bootstrap.write() = BootstrapState::default();
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(
+                forward_arti_output(
+                    stdout,
+                    self.diagnostics.clone(),
+                    self.bootstrap.clone(),
+                    self.descriptor.clone(),
+                )
+                .in_current_span(),
+            );
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(
+                forward_arti_output(
+                    stderr,
+                    self.diagnostics.clone(),
+                    self.bootstrap.clone(),
+                    self.descriptor.clone(),
+                )
+                .in_current_span(),
+            );
+        }
+        Ok(child)
+    }
+}
+
+/// Launches arti with our own stdin, stdout and stderr, so its console output reaches the
+/// platform untouched (`--foreground-arti`).
+///
+/// Nothing reads that output, so bootstrap progress and descriptor uploads go unreported.
+pub struct ForegroundArti(pub Arti);
+
+impl ArtiLauncher for ForegroundArti {
+    type Process = tokio::process::Child;
+
+    fn launch(&mut self) -> std::io::Result<Self::Process> {
+        let child = Command::new(&self.0.binary)
+            .current_dir(&self.0.dir)
+            .arg("proxy")
+            .arg("-c")
+            .arg(&self.0.config)
+            .kill_on_drop(true)
+            .spawn()?;
+        // Progress can't be followed without arti's output; report it as done rather than
+        // leaving the landing pages stuck at 0%
+        *self.0.bootstrap.write() = BootstrapState::complete();
+        Ok(child)
+    }
+}
+
+/// Splits a line of arti's console log into its level and message.
+///
+/// arti logs as `<timestamp>  <LEVEL> <module>: <message>`; the timestamp is dropped since ours
+/// is added on top. Lines in any other shape (panics, usage errors) are passed through whole
+/// as warnings.
+fn parse_arti_line(line: &str) -> (tracing::Level, &str) {
+    let mut parts = line.trim_start().splitn(2, char::is_whitespace);
+    let (Some(_timestamp), Some(rest)) = (parts.next(), parts.next()) else {
+        return (tracing::Level::WARN, line);
+    };
+    let rest = rest.trim_start();
+    let (level, message) = rest.split_once(' ').unwrap_or((rest, ""));
+    let level = match level {
+        "ERROR" => tracing::Level::ERROR,
+        "WARN" => tracing::Level::WARN,
+        "INFO" => tracing::Level::INFO,
+        "DEBUG" => tracing::Level::DEBUG,
+        "TRACE" => tracing::Level::TRACE,
+        _ => return (tracing::Level::WARN, line),
+    };
+    (level, message.trim_start())
+}
+
+/// How far arti has come in bootstrapping its connection to the Tor network.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BootstrapState {
+    /// 0 to 100
+    pub percent: u8,
+    /// What arti is currently doing, as it last reported; `None` before the first report
+    pub phase: Option<String>,
+}
+
+impl BootstrapState {
+    /// Extracts bootstrap progress from a log message.
+    ///
+    /// arti reports progress as `<percent>%: <phase>` (e.g. `40%: connecting successfully;
+    /// fetching a consensus`) and announces completion with `Sufficiently bootstrapped`.
+    fn parse(message: &str) -> Option<Self> {
+        if message.contains("Sufficiently bootstrapped") {
+            return Some(Self::complete());
+        }
+        let (head, phase) = message.split_once("%: ")?;
+        let digits = head.rsplit(|c: char| !c.is_ascii_digit()).next()?;
+        let percent = digits.parse().ok().filter(|percent| *percent <= 100)?;
+        Some(Self {
+            percent,
+            phase: Some(phase.trim().to_string()),
+        })
+    }
+
+    pub fn complete() -> Self {
+        Self {
+            percent: 100,
+            phase: Some("done".to_string()),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.percent == 100
+    }
+}
+
+impl std::fmt::Display for BootstrapState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.phase {
+            Some(phase) => write!(f, "{}% ({phase})", self.percent),
+            None => write!(f, "{}%", self.percent),
+        }
+    }
+}
+
+/// How long an onion service descriptor stays valid on the HSDirs.
+const DESCRIPTOR_LIFETIME: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// Slack past the scheduled refresh before a republish counts as overdue; arti gives a single
+/// upload round up to five minutes.
+const DESCRIPTOR_REFRESH_GRACE: Duration = Duration::from_secs(10 * 60);
+
+/// Publication of the onion service descriptor, as reported in arti's log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DescriptorState {
+    /// When arti last finished an upload round
+    pub published: Option<SystemTime>,
+    /// When arti scheduled the next upload
+    pub refresh_due: Option<SystemTime>,
+}
+
+impl DescriptorState {
+    /// Extracts the republish delay from a log message.
+    ///
+    /// After every upload round arti logs `reuploading descriptor in <duration>`, with the
+    /// duration in humantime form (e.g. `1h 23m`) and picked between one and two hours out.
+    fn parse_republish(message: &str) -> Option<Duration> {
+        let (_, rest) = message.split_once("reuploading descriptor in ")?;
+        let mut delay = None;
+        for part in rest.split_whitespace() {
+            let split = part
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(part.len());
+            let (Ok(value), unit) = (part[..split].parse::<u64>(), &part[split..]) else {
+                break;
+            };
+            let unit = match unit {
+                "h" => 60 * 60,
+                "m" => 60,
+                "s" => 1,
+                _ => break,
+            };
+            delay = Some(delay.unwrap_or(Duration::ZERO) + Duration::from_secs(value * unit));
+        }
+        delay
+    }
+
+    /// Records an upload round finishing at `now`, with the next one due after `refresh_in`.
+    fn published(&mut self, now: SystemTime, refresh_in: Duration) {
+        self.published = Some(now);
+        self.refresh_due = Some(now + refresh_in);
+    }
+
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.published
+            .map(|published| published + DESCRIPTOR_LIFETIME)
+    }
+
+    /// How long the scheduled refresh has been missed by, once past the grace period.
+    pub fn overdue(&self, now: SystemTime) -> Option<Duration> {
+        let late = now.duration_since(self.refresh_due?).ok()?;
+        (late > DESCRIPTOR_REFRESH_GRACE).then_some(late)
+    }
+}
+
+/// Streams one of arti's output pipes into our log under the `arti` target, keeping its tail
+/// for crash dumps and picking up bootstrap progress and descriptor uploads along the way.
+async fn forward_arti_output(
+    output: impl tokio::io::AsyncRead + Unpin,
+    diagnostics: Arc<Diagnostics>,
+    bootstrap: Arc<RwLock<BootstrapState>>,
+    descriptor: Arc<RwLock<DescriptorState>>,
+) {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let (level, message) = parse_arti_line(&line);
+        match level {
+            tracing::Level::ERROR => error!(target: "arti", "{message}"),
+            tracing::Level::WARN => warn!(target: "arti", "{message}"),
+            tracing::Level::INFO => info!(target: "arti", "{message}"),
+            _ => debug!(target: "arti", "{message}"),
+        }
+        if let Some(progress) = BootstrapState::parse(message) {
+            *bootstrap.write() = progress;
+        }
+        if let Some(refresh_in) = DescriptorState::parse_republish(message) {
+            descriptor.write().published(SystemTime::now(), refresh_in);
+        }
+        diagnostics.arti_output(line);
+    }
+}
+
+impl ArtiProcess for tokio::process::Child {
+    fn wait(&mut self) -> impl Future<Output = std::io::Result<ExitStatus>> + Send {
+        tokio::process::Child::wait(self)
+    }
+
+    fn terminate(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.id() {
+            use nix::sys::signal::{kill, Signal};
+            use nix::unistd::Pid;
+
+            let _ = kill(Pid::from_raw(pid as i32), Signal::SIGTERM);
+            return;
+        }
+        let _ = self.start_kill();
+    }
+
+    async fn kill(&mut self) {
+        let _ = self.start_kill();
+        let _ = tokio::process::Child::wait(self).await;
+    }
+}
+
+/// Keeps arti running, relaunching it with backoff until `policy` gives up.
+///
+/// Drives a `SupervisorState` machine with the events produced by `launcher` and `shutdown`.
+/// Every status change is published on `status`, allowing dependents (onion address discovery,
+/// the onion endpoint's outage page) to react as soon as arti is spawned or goes down. Each
+/// launch waits for a slot in `processes`, held until that arti exits. `restarts` bounces a
+/// running arti, or cuts a backoff short.
+#[allow(clippy::too_many_arguments)]
+pub async fn supervise_arti<L: ArtiLauncher>(
+    mut launcher: L,
+    processes: ProcessBudget,
+    status: watch::Sender<ArtiStatus>,
+    shutdown: Shutdown,
+    timings: Arc<ShutdownTimings>,
+    log: Arc<LogThrottle>,
+    diagnostics: Arc<Diagnostics>,
+    mut policy: RestartPolicy,
+    restarts: RestartRequests,
+    watchdog: Option<Watchdog>,
+) -> Result<(), ()> {
+    let mut shutdown_signal = shutdown.subscribe();
+    let mut process: Option<L::Process> = None;
+    // Slot in the process budget held by `process`
+    let mut _slot: Option<OwnedSemaphorePermit> = None;
+    let mut state = SupervisorState::Idle;
+
+    loop {
+        diagnostics.event(format_args!("supervisor {state}"));
+        debug!(state = %state, "supervisor state changed");
+        if let Some(next) = state.status() {
+            status.send_if_modified(|current| std::mem::replace(current, next) != next);
+        }
+
+        let event = match state {
+            SupervisorState::Idle => SupervisorEvent::Start,
+            SupervisorState::Spawning { attempt } => {
+                if attempt > 1 {
+                    policy.relaunching();
+                    info!(attempt, "restarting arti");
+                }
+                let acquired = tokio::select! {
+                    acquired = processes.acquire() => Some(acquired),
+                    _ = shutdown_signal.recv() => None,
+                };
+                match acquired.map(|acquired| (launcher.launch(), acquired)) {
+                    None => SupervisorEvent::ShutdownRequested,
+                    Some((Ok(child), acquired)) => {
+                        process = Some(child);
+                        _slot = Some(acquired);
+                        SupervisorEvent::Spawned
+                    }
+                    Some((Err(err), _)) => {
+                        log.error(format!("failed to spawn arti: {:?}", err));
+                        SupervisorEvent::SpawnFailed
+                    }
+                }
+            }
+            SupervisorState::Running { .. } => {
+                let child = process.as_mut().expect("running state has a process");
+                tokio::select! {
+                    exit = child.wait() => {
+                        match exit {
+                            Ok(exit) if exit.success() => {
+                                log.error("arti exited successfully (unexpected), will relaunch after backoff".to_string());
+                            }
+                            Ok(exit) => log.error(format!("arti exited with status {:?}", exit.code())),
+                            Err(err) => log.error(format!("failed to wait on arti: {:?}", err)),
+                        }
+                        process = None;
+                        _slot = None;
+                        SupervisorEvent::Exited
+                    }
+                    _ = chaos_arti_kill() => {
+                        warn!("chaos: killing arti");
+                        child.kill().await;
+                        process = None;
+                        _slot = None;
+                        SupervisorEvent::Exited
+                    }
+                    _ = restarts.requested() => {
+                        info!("restarting arti on request");
+                        child.kill().await;
+                        process = None;
+                        _slot = None;
+                        SupervisorEvent::RestartRequested
+                    }
+                    _ = Watchdog::tripped(watchdog.as_ref()) => {
+                        log.error("arti stopped answering on its SOCKS port, killing it".to_string());
+                        child.kill().await;
+                        process = None;
+                        _slot = None;
+                        SupervisorEvent::Exited
+                    }
+                    _ = shutdown_signal.recv() => SupervisorEvent::ShutdownRequested,
+                }
+            }
+            SupervisorState::Backoff { until, .. } => {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(until) => SupervisorEvent::BackoffElapsed,
+                    _ = restarts.requested() => SupervisorEvent::RestartRequested,
+                    _ = shutdown_signal.recv() => SupervisorEvent::ShutdownRequested,
+                }
+            }
+            SupervisorState::Exhausted => {
+                log.flush();
+                error!(
+                    failures = policy.limits.max_failures,
+                    window_secs = policy.limits.failure_window.as_secs(),
+                    "arti keeps failing, requesting shutdown"
+                );
+                shutdown.trigger(ShutdownReason::ArtiExhausted);
+                return Err(());
+            }
+            SupervisorState::ShuttingDown => {
+                timings.begin();
+                log.flush();
+                if let Some(mut child) = process.take() {
+                    stop_arti(&mut child, &mut shutdown_signal).await;
+                }
+                timings.record(ShutdownEvent::ArtiExited);
+                return Ok(());
+            }
+        };
+
+        state = state.on(event, Instant::now(), &mut policy);
+    }
+}
+
+/// Asks arti to exit, killing it if it outlasts the shutdown grace period.
+async fn stop_arti(child: &mut impl ArtiProcess, shutdown_signal: &mut ShutdownSignal) {
+    // SIGTERM lets arti withdraw its onion service before it goes away
+    child.terminate();
+    tokio::select! {
+        _ = child.wait() => {}
+        _ = shutdown_signal.recv_forced() => {
+            warn!("arti did not exit within the shutdown grace period, killing it");
+            child.kill().await;
+        }
+    }
+}
+
+/// Runs a single arti without relaunching it, for deployments where the platform (Railway,
+/// systemd) owns restarts.
+///
+/// arti exiting for any reason other than shutdown requests shutdown and returns `Err`, so the
+/// whole process exits with it.
+pub async fn run_arti_foreground<L: ArtiLauncher>(
+    mut launcher: L,
+    processes: ProcessBudget,
+    status: watch::Sender<ArtiStatus>,
+    shutdown: Shutdown,
+    timings: Arc<ShutdownTimings>,
+    diagnostics: Arc<Diagnostics>,
+) -> Result<(), ()> {
+    let mut shutdown_signal = shutdown.subscribe();
+    let _slot = tokio::select! {
+        slot = processes.acquire() => slot,
+        _ = shutdown_signal.recv() => {
+            timings.record(ShutdownEvent::ArtiExited);
+            return Ok(());
+        }
+    };
+    let mut child = match launcher.launch() {
+        Ok(child) => child,
+        Err(err) => {
+            error!("failed to spawn arti: {:?}", err);
+            status.send_replace(ArtiStatus::Exhausted);
+            shutdown.trigger(ShutdownReason::ArtiExited);
+            return Err(());
+        }
+    };
+    diagnostics.event("arti launched in the foreground");
+    status.send_replace(ArtiStatus::Running);
+
+    tokio::select! {
+        exit = child.wait() => {
+            match exit {
+                Ok(exit) => error!(code = ?exit.code(), "arti exited, shutting down"),
+                Err(err) => error!("failed to wait on arti: {:?}", err),
+            }
+            diagnostics.event("arti exited in the foreground");
+            status.send_replace(ArtiStatus::Exhausted);
+            shutdown.trigger(ShutdownReason::ArtiExited);
+            Err(())
+        }
+        _ = shutdown_signal.recv() => {
+            timings.begin();
+            stop_arti(&mut child, &mut shutdown_signal).await;
+            timings.record(ShutdownEvent::ArtiExited);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    use parking_lot::Mutex;
+    use tokio::time::sleep;
+
+    const ALL_EVENTS: [SupervisorEvent; 7] = [
+        SupervisorEvent::Start,
+        SupervisorEvent::Spawned,
+        SupervisorEvent::SpawnFailed,
+        SupervisorEvent::Exited,
+        SupervisorEvent::BackoffElapsed,
+        SupervisorEvent::RestartRequested,
+        SupervisorEvent::ShutdownRequested,
+    ];
+
+    /// A policy without jitter, so backoffs are exact.
+    fn policy() -> RestartPolicy {
+        RestartPolicy::new(|| 0.0)
+    }
+
+    #[test]
+    fn supervisor_launches_and_relaunches_after_backoff() {
+        let mut policy = policy();
+        let now = Instant::now();
+        let state = SupervisorState::Idle.on(SupervisorEvent::Start, now, &mut policy);
+        assert_eq!(state, SupervisorState::Spawning { attempt: 1 });
+        let state = state.on(SupervisorEvent::Spawned, now, &mut policy);
+        assert_eq!(
+            state,
+            SupervisorState::Running {
+                attempt: 1,
+                since: now
+            }
+        );
+
+        let state = state.on(SupervisorEvent::Exited, now, &mut policy);
+        let until = now + ARTI_BACKOFF_BASE;
+        assert_eq!(state, SupervisorState::Backoff { attempt: 1, until });
+
+        // A wakeup before the deadline does not cut the backoff short
+        assert_eq!(
+            state.on(SupervisorEvent::BackoffElapsed, now, &mut policy),
+            state
+        );
+        assert_eq!(
+            state.on(SupervisorEvent::BackoffElapsed, until, &mut policy),
+            SupervisorState::Spawning { attempt: 2 }
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_until_the_window_budget_is_spent() {
+        let mut policy = policy();
+        let now = Instant::now();
+        for failure in 0..ARTI_MAX_FAILURES - 1 {
+            assert_eq!(
+                policy.failed(now),
+                Some(ARTI_BACKOFF_BASE * 2u32.pow(failure as u32))
+            );
+        }
+        assert_eq!(policy.failed(now), None);
+    }
+
+    #[test]
+    fn supervisor_exhausts_once_the_policy_gives_up() {
+        let now = Instant::now();
+        let mut policy = policy();
+        for _ in 1..ARTI_MAX_FAILURES {
+            policy.failed(now);
+        }
+        assert_eq!(
+            SupervisorState::Spawning { attempt: 9 }.on(
+                SupervisorEvent::SpawnFailed,
+                now,
+                &mut policy.clone()
+            ),
+            SupervisorState::Exhausted
+        );
+        assert_eq!(
+            SupervisorState::Running {
+                attempt: 9,
+                since: now
+            }
+            .on(SupervisorEvent::Exited, now, &mut policy),
+            SupervisorState::Exhausted
+        );
+    }
+
+    #[test]
+    fn failures_outside_the_window_are_forgotten() {
+        let mut policy = policy();
+        let mut now = Instant::now();
+        // Far more failures than the budget, but never too many at once
+        for _ in 0..ARTI_MAX_FAILURES * 3 {
+            assert!(policy.failed(now).is_some());
+            now += ARTI_FAILURE_WINDOW / (ARTI_MAX_FAILURES as u32 - 1);
+        }
+    }
+
+    #[test]
+    fn stable_run_resets_the_backoff() {
+        let mut policy = policy();
+        let now = Instant::now();
+        for _ in 1..ARTI_MAX_FAILURES {
+            policy.failed(now);
+        }
+
+        let exited = now + ARTI_STABLE_RUN;
+        let state = SupervisorState::Running {
+            attempt: 5,
+            since: now,
+        }
+        .on(SupervisorEvent::Exited, exited, &mut policy);
+        assert_eq!(
+            state,
+            SupervisorState::Backoff {
+                attempt: 5,
+                until: exited + ARTI_BACKOFF_BASE
+            }
+        );
+
+        // A short run is not enough
+        let mut policy = self::policy();
+        policy.failed(now);
+        policy.ran(now, now + ARTI_STABLE_RUN / 2);
+        assert_eq!(policy.failed(now), Some(ARTI_BACKOFF_BASE * 2));
+    }
+
+    #[test]
+    fn journaled_failures_survive_a_wrapper_restart() {
+        let wall = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let now = Instant::now();
+        let mut earlier = policy();
+        earlier.restore(&[], now, wall);
+        earlier.failed(now);
+        earlier.failed(now + Duration::from_secs(3));
+        let recorded = earlier.recorded();
+        assert_eq!(
+            recorded,
+            [1_700_000_000_000, 1_700_000_003_000],
+            "recorded as Unix milliseconds"
+        );
+
+        // Restarted a few seconds later: the backoff owed for the second failure still applies
+        let restarted_at = now + Duration::from_secs(5);
+        let mut restarted = policy();
+        restarted.restore(&recorded, restarted_at, wall + Duration::from_secs(5));
+        assert_eq!(restarted.failures.len(), 2);
+        assert_eq!(restarted.recorded(), recorded);
+        assert_eq!(
+            SupervisorState::Idle.on(SupervisorEvent::Start, restarted_at, &mut restarted),
+            SupervisorState::Backoff {
+                attempt: 0,
+                until: now + Duration::from_secs(3) + ARTI_BACKOFF_BASE * 2,
+            }
+        );
+
+        // Failures that have left the window are dropped on the way in
+        let mut much_later = policy();
+        much_later.restore(
+            &recorded,
+            now,
+            wall + ARTI_FAILURE_WINDOW + Duration::from_secs(3),
+        );
+        assert!(much_later.failures.is_empty());
+        assert_eq!(
+            SupervisorState::Idle.on(SupervisorEvent::Start, now, &mut much_later),
+            SupervisorState::Spawning { attempt: 1 }
+        );
+    }
+
+    #[test]
+    fn spent_budget_carried_over_waits_for_the_window() {
+        let wall = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let now = Instant::now();
+        let recorded: Vec<u64> = (0..ARTI_MAX_FAILURES as u64)
+            .map(|minute| 1_700_000_000_000 + minute * 60_000)
+            .collect();
+        let mut restarted = policy();
+        let restarted_at = now + Duration::from_secs(5 * 60);
+        restarted.restore(&recorded, restarted_at, wall + Duration::from_secs(5 * 60));
+        // The oldest failure has to age out before arti may be launched again
+        assert_eq!(
+            restarted.resume_at(restarted_at),
+            Some(now + ARTI_FAILURE_WINDOW)
+        );
+    }
+
+    #[test]
+    fn jitter_shortens_backoff_by_at_most_half() {
+        let now = Instant::now();
+        assert_eq!(
+            RestartPolicy::new(|| 1.0).failed(now),
+            Some(ARTI_BACKOFF_BASE / 2)
+        );
+        assert_eq!(
+            RestartPolicy::new(|| 0.5).failed(now),
+            Some(ARTI_BACKOFF_BASE.mul_f64(0.75))
+        );
+    }
+
+    #[test]
+    fn supervisor_shuts_down_from_every_live_state() {
+        let now = Instant::now();
+        for state in [
+            SupervisorState::Idle,
+            SupervisorState::Spawning { attempt: 1 },
+            SupervisorState::Running {
+                attempt: 1,
+                since: now,
+            },
+            SupervisorState::Backoff {
+                attempt: 1,
+                until: now + ARTI_BACKOFF_BASE,
+            },
+        ] {
+            assert_eq!(
+                state.on(SupervisorEvent::ShutdownRequested, now, &mut policy()),
+                SupervisorState::ShuttingDown,
+                "{state:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn supervisor_terminal_states_ignore_every_event() {
+        let later = Instant::now() + Duration::from_secs(3600);
+        for state in [SupervisorState::Exhausted, SupervisorState::ShuttingDown] {
+            for event in ALL_EVENTS {
+                assert_eq!(
+                    state.on(event, later, &mut policy()),
+                    state,
+                    "{state:?} on {event:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn supervisor_ignores_events_that_do_not_apply() {
+        use SupervisorEvent::*;
+
+        let now = Instant::now();
+        let until = now + ARTI_BACKOFF_BASE;
+        let cases: [(SupervisorState, &[SupervisorEvent]); 4] = [
+            (SupervisorState::Idle, &[Start]),
+            (
+                SupervisorState::Spawning { attempt: 1 },
+                &[Spawned, SpawnFailed],
+            ),
+            (
+                SupervisorState::Running {
+                    attempt: 1,
+                    since: now,
+                },
+                &[Exited, RestartRequested],
+            ),
+            (
+                SupervisorState::Backoff { attempt: 1, until },
+                &[BackoffElapsed, RestartRequested],
+            ),
+        ];
+        for (state, handled) in cases {
+            for event in ALL_EVENTS
+                .into_iter()
+                .filter(|event| !handled.contains(event) && *event != ShutdownRequested)
+            {
+                assert_eq!(
+                    state.on(event, until, &mut policy()),
+                    state,
+                    "{state:?} on {event:?}"
+                );
+            }
+        }
+    }
+
+    /// Scripted outcome of one fake arti launch.
+    #[derive(Debug, Clone, Copy)]
+    enum FakeLaunch {
+        SpawnFails,
+        ExitsAfter(Duration, i32),
+        /// Runs until told to exit
+        RunsUntilStopped,
+        /// Runs until killed, ignoring requests to exit
+        IgnoresTerminate,
+    }
+
+    /// Launcher that replays a script, then keeps arti running once the script is used up.
+    #[derive(Debug, Clone, Default)]
+    struct FakeArti {
+        script: Arc<Mutex<std::collections::VecDeque<FakeLaunch>>>,
+        launches: Arc<AtomicUsize>,
+        terminations: Arc<AtomicUsize>,
+        kills: Arc<AtomicUsize>,
+    }
+
+    impl FakeArti {
+        fn new(script: impl IntoIterator<Item = FakeLaunch>) -> Self {
+            Self {
+                script: Arc::new(Mutex::new(script.into_iter().collect())),
+                ..Default::default()
+            }
+        }
+    }
+
+    struct FakeProcess {
+        outcome: FakeLaunch,
+        terminated: bool,
+        terminations: Arc<AtomicUsize>,
+        kills: Arc<AtomicUsize>,
+    }
+
+    impl ArtiLauncher for FakeArti {
+        type Process = FakeProcess;
+
+        fn launch(&mut self) -> std::io::Result<FakeProcess> {
+            self.launches.fetch_add(1, Ordering::SeqCst);
+            match self
+                .script
+                .lock()
+                .pop_front()
+                .unwrap_or(FakeLaunch::RunsUntilStopped)
+            {
+                FakeLaunch::SpawnFails => Err(std::io::Error::other("no such binary")),
+                outcome => Ok(FakeProcess {
+                    outcome,
+                    terminated: false,
+                    terminations: self.terminations.clone(),
+                    kills: self.kills.clone(),
+                }),
+            }
+        }
+    }
+
+    impl ArtiProcess for FakeProcess {
+        async fn wait(&mut self) -> std::io::Result<ExitStatus> {
+            use std::os::unix::process::ExitStatusExt;
+
+            match self.outcome {
+                FakeLaunch::ExitsAfter(after, code) => {
+                    sleep(after).await;
+                    Ok(ExitStatus::from_raw(code << 8))
+                }
+                FakeLaunch::RunsUntilStopped if self.terminated => Ok(ExitStatus::from_raw(0)),
+                _ => std::future::pending().await,
+            }
+        }
+
+        fn terminate(&mut self) {
+            self.terminated = true;
+            self.terminations.fetch_add(1, Ordering::SeqCst);
+        }
+
+        async fn kill(&mut self) {
+            self.kills.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn spawn_supervisor(
+        arti: FakeArti,
+        shutdown: &Shutdown,
+    ) -> (
+        tokio::task::JoinHandle<Result<(), ()>>,
+        watch::Receiver<ArtiStatus>,
+    ) {
+        spawn_supervisor_with(
+            arti,
+            shutdown,
+            ProcessBudget::new(1),
+            Default::default(),
+            None,
+        )
+    }
+
+    fn spawn_supervisor_with(
+        arti: FakeArti,
+        shutdown: &Shutdown,
+        processes: ProcessBudget,
+        restarts: RestartRequests,
+        watchdog: Option<Watchdog>,
+    ) -> (
+        tokio::task::JoinHandle<Result<(), ()>>,
+        watch::Receiver<ArtiStatus>,
+    ) {
+        let (status_tx, status_rx) = watch::channel(ArtiStatus::Starting);
+        let handle = tokio::spawn(supervise_arti(
+            arti,
+            processes,
+            status_tx,
+            shutdown.clone(),
+            Arc::new(ShutdownTimings::default()),
+            Arc::new(LogThrottle::new(Duration::ZERO)),
+            Arc::new(Diagnostics::new()),
+            policy(),
+            restarts,
+            watchdog,
+        ));
+        (handle, status_rx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn crash_loop_exhausts_and_requests_shutdown() {
+        let crash = FakeLaunch::ExitsAfter(Duration::from_secs(1), 1);
+        let arti = FakeArti::new([FakeLaunch::SpawnFails, crash, crash, crash, crash]);
+        let shutdown = Shutdown::new();
+        let started = Instant::now();
+
+        let (handle, status) = spawn_supervisor(arti.clone(), &shutdown);
+        assert_eq!(handle.await.unwrap(), Err(()));
+
+        assert_eq!(arti.launches.load(Ordering::SeqCst), ARTI_MAX_FAILURES);
+        assert_eq!(*status.borrow(), ArtiStatus::Exhausted);
+        tokio::time::timeout(Duration::ZERO, shutdown.subscribe().recv())
+            .await
+            .expect("the supervisor should have requested shutdown");
+        // Four crashes after a second each, with a doubling backoff after every failure but the last
+        let backoffs: Duration = (0..ARTI_MAX_FAILURES as u32 - 1)
+            .map(|failure| ARTI_BACKOFF_BASE * 2u32.pow(failure))
+            .sum();
+        assert_eq!(started.elapsed(), Duration::from_secs(4) + backoffs);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn arti_waits_for_a_free_process_slot() {
+        let arti = FakeArti::new([FakeLaunch::ExitsAfter(Duration::from_secs(1), 1)]);
+        let processes = ProcessBudget::new(1);
+        let shutdown = Shutdown::new();
+
+        let busy = processes.acquire().await;
+        let (handle, _status) = spawn_supervisor_with(
+            arti.clone(),
+            &shutdown,
+            processes.clone(),
+            Default::default(),
+            None,
+        );
+        sleep(Duration::from_secs(5)).await;
+        assert_eq!(arti.launches.load(Ordering::SeqCst), 0);
+
+        drop(busy);
+        sleep(Duration::from_millis(1)).await;
+        assert_eq!(arti.launches.load(Ordering::SeqCst), 1);
+        // The crashed arti gives its slot back before the relaunch
+        sleep(Duration::from_secs(1) + ARTI_BACKOFF_BASE).await;
+        assert_eq!(arti.launches.load(Ordering::SeqCst), 2);
+        assert!(processes.slots.try_acquire().is_err());
+
+        shutdown.trigger(ShutdownReason::Interrupt);
+        assert_eq!(handle.await.unwrap(), Ok(()));
+        assert!(processes.slots.try_acquire().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn supervisor_recovers_after_a_crash() {
+        let arti = FakeArti::new([FakeLaunch::ExitsAfter(Duration::from_secs(1), 1)]);
+        let shutdown = Shutdown::new();
+        let (handle, mut status) = spawn_supervisor(arti.clone(), &shutdown);
+
+        status
+            .wait_for(|status| matches!(status, ArtiStatus::Backoff { .. }))
+            .await
+            .unwrap();
+        status
+            .wait_for(|status| *status == ArtiStatus::Running)
+            .await
+            .unwrap();
+        assert_eq!(arti.launches.load(Ordering::SeqCst), 2);
+
+        shutdown.trigger(ShutdownReason::Terminate);
+        assert_eq!(handle.await.unwrap(), Ok(()));
+        // arti exited when asked to, so it was never killed
+        assert_eq!(arti.terminations.load(Ordering::SeqCst), 1);
+        assert_eq!(arti.kills.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn arti_is_killed_once_the_grace_period_runs_out() {
+        let arti = FakeArti::new([FakeLaunch::IgnoresTerminate]);
+        let shutdown = Shutdown::new().with_grace(Duration::from_secs(10));
+        let (handle, mut status) = spawn_supervisor(arti.clone(), &shutdown);
+
+        status
+            .wait_for(|status| *status == ArtiStatus::Running)
+            .await
+            .unwrap();
+        let requested = Instant::now();
+        shutdown.trigger(ShutdownReason::Terminate);
+        assert_eq!(handle.await.unwrap(), Ok(()));
+
+        assert_eq!(requested.elapsed(), Duration::from_secs(10));
+        assert_eq!(arti.terminations.load(Ordering::SeqCst), 1);
+        assert_eq!(arti.kills.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requested_restart_relaunches_arti_at_once() {
+        let arti = FakeArti::new([FakeLaunch::RunsUntilStopped]);
+        let shutdown = Shutdown::new();
+        let restarts = RestartRequests::default();
+        let (handle, mut status) = spawn_supervisor_with(
+            arti.clone(),
+            &shutdown,
+            ProcessBudget::new(1),
+            restarts.clone(),
+            None,
+        );
+
+        status
+            .wait_for(|status| *status == ArtiStatus::Running)
+            .await
+            .unwrap();
+        let requested = Instant::now();
+        restarts.request();
+        // The relaunch is immediate, so the backoff in between may already be overwritten
+        status.changed().await.unwrap();
+        status
+            .wait_for(|status| *status == ArtiStatus::Running)
+            .await
+            .unwrap();
+        assert_eq!(requested.elapsed(), Duration::ZERO);
+        assert_eq!(arti.kills.load(Ordering::SeqCst), 1);
+        assert_eq!(arti.launches.load(Ordering::SeqCst), 2);
+
+        shutdown.trigger(ShutdownReason::Terminate);
+        assert_eq!(handle.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hung_arti_is_restarted_by_the_watchdog() {
+        // Nothing listens here any more, so every probe is refused
+        let socks = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let watchdog = Watchdog {
+            socks,
+            interval: Duration::from_secs(10),
+            max_failures: 3,
+        };
+        let arti = FakeArti::new([FakeLaunch::RunsUntilStopped]);
+        let shutdown = Shutdown::new();
+        let (handle, mut status) = spawn_supervisor_with(
+            arti.clone(),
+            &shutdown,
+            ProcessBudget::new(1),
+            Default::default(),
+            Some(watchdog),
+        );
+
+        status
+            .wait_for(|status| matches!(status, ArtiStatus::Backoff { .. }))
+            .await
+            .unwrap();
+        assert_eq!(arti.kills.load(Ordering::SeqCst), 1);
+        status
+            .wait_for(|status| *status == ArtiStatus::Running)
+            .await
+            .unwrap();
+        assert_eq!(arti.launches.load(Ordering::SeqCst), 2);
+
+        shutdown.trigger(ShutdownReason::Terminate);
+        assert_eq!(handle.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn foreground_arti_is_never_relaunched() {
+        let arti = FakeArti::new([FakeLaunch::ExitsAfter(Duration::from_secs(1), 0)]);
+        let shutdown = Shutdown::new();
+        let (status_tx, status) = watch::channel(ArtiStatus::Starting);
+        let result = run_arti_foreground(
+            arti.clone(),
+            ProcessBudget::new(1),
+            status_tx,
+            shutdown.clone(),
+            Arc::new(ShutdownTimings::default()),
+            Arc::new(Diagnostics::new()),
+        )
+        .await;
+
+        assert_eq!(result, Err(()));
+        assert_eq!(*status.borrow(), ArtiStatus::Exhausted);
+        assert_eq!(shutdown.reason(), Some(ShutdownReason::ArtiExited));
+        assert_eq!(arti.launches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_interrupts_backoff() {
+        let arti = FakeArti::new([FakeLaunch::ExitsAfter(Duration::from_secs(1), 1)]);
+        let shutdown = Shutdown::new();
+        let started = Instant::now();
+        let (handle, mut status) = spawn_supervisor(arti.clone(), &shutdown);
+
+        status
+            .wait_for(|status| matches!(status, ArtiStatus::Backoff { .. }))
+            .await
+            .unwrap();
+        shutdown.trigger(ShutdownReason::Terminate);
+        assert_eq!(handle.await.unwrap(), Ok(()));
+
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+        assert_eq!(arti.launches.load(Ordering::SeqCst), 1);
+        // Nothing was running, so there was nothing to stop
+        assert_eq!(arti.terminations.load(Ordering::SeqCst), 0);
+        assert_eq!(arti.kills.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn arti_log_lines_keep_their_level() {
+        use tracing::Level;
+
+        assert_eq!(
+            parse_arti_line("2025-01-01T12:00:00.000000Z  INFO arti::proxy: Listening on 9150"),
+            (Level::INFO, "arti::proxy: Listening on 9150")
+        );
+        assert_eq!(
+            parse_arti_line("2025-01-01T12:00:00Z ERROR tor_hsservice: descriptor upload failed"),
+            (Level::ERROR, "tor_hsservice: descriptor upload failed")
+        );
+        // Anything that isn't a log line is surfaced whole
+        for line in [
+            "thread 'main' panicked at src/main.rs:1:1",
+            "error: 1 2",
+            "",
+        ] {
+            assert_eq!(parse_arti_line(line), (Level::WARN, line));
+        }
+    }
+
+    #[test]
+    fn bootstrap_progress_is_read_from_arti_log() {
+        assert_eq!(
+            BootstrapState::parse(
+                "arti_client::status: 40%: connecting successfully; fetching a consensus"
+            ),
+            Some(BootstrapState {
+                percent: 40,
+                phase: Some("connecting successfully; fetching a consensus".to_string()),
+            })
+        );
+        assert_eq!(
+            BootstrapState::parse(
+                "arti::reload_cfg: Sufficiently bootstrapped; system SOCKS now functional."
+            ),
+            Some(BootstrapState::complete())
+        );
+        for message in [
+            "arti::proxy: Listening on 9150",
+            "tor_dirmgr: 250%: nonsense",
+            "tor_guardmgr: %: no percentage",
+        ] {
+            assert_eq!(BootstrapState::parse(message), None);
+        }
+    }
+
+    #[test]
+    fn descriptor_republish_delay_is_read_from_arti_log() {
+        assert_eq!(
+            DescriptorState::parse_republish(
+                "tor_hsservice::publish::reactor: reuploading descriptor in 1h 23m time_period=TimePeriod { interval_num: 20000 }"
+            ),
+            Some(Duration::from_secs(83 * 60))
+        );
+        assert_eq!(
+            DescriptorState::parse_republish("reuploading descriptor in 2h"),
+            Some(Duration::from_secs(2 * 60 * 60))
+        );
+        assert_eq!(
+            DescriptorState::parse_republish("reuploading descriptor in soon"),
+            None
+        );
+        assert_eq!(
+            DescriptorState::parse_republish("arti::proxy: Listening on 9150"),
+            None
+        );
+    }
+
+    #[test]
+    fn descriptor_refresh_is_overdue_after_the_grace_period() {
+        let published = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut descriptor = DescriptorState::default();
+        assert_eq!(descriptor.overdue(published), None);
+
+        descriptor.published(published, Duration::from_secs(60 * 60));
+        assert_eq!(descriptor.expires(), Some(published + DESCRIPTOR_LIFETIME));
+        let due = published + Duration::from_secs(60 * 60);
+        assert_eq!(descriptor.overdue(due), None);
+        assert_eq!(descriptor.overdue(due + DESCRIPTOR_REFRESH_GRACE), None);
+        let late = DESCRIPTOR_REFRESH_GRACE + Duration::from_secs(1);
+        assert_eq!(descriptor.overdue(due + late), Some(late));
+
+        // A republish starts the countdown over
+        descriptor.published(due + late, Duration::from_secs(90 * 60));
+        assert_eq!(descriptor.overdue(due + late), None);
+    }
+}
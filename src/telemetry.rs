@@ -0,0 +1,221 @@
+//! Names, labels and descriptions of the metrics the server records.
+//!
+//! Metrics go through the `metrics` facade to whatever recorder is installed globally. A
+//! standalone server installs a Prometheus recorder for `/metrics`; an application embedding the
+//! crate that installed its own recorder first keeps it, and gets these metrics there instead.
+
+use metrics::Unit;
+
+/// How a metric is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// A metric the server records.
+#[derive(Debug)]
+pub struct Metric {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub unit: Option<Unit>,
+    /// Label keys every sample carries
+    pub labels: &'static [&'static str],
+    pub description: &'static str,
+    /// Suggested histogram bucket bounds; empty for the exporter's default
+    pub buckets: &'static [f64],
+}
+
+/// Histogram buckets, in seconds, for request latencies.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub const HTTP_REQUESTS: Metric = Metric {
+    name: "http_requests_total",
+    kind: MetricKind::Counter,
+    unit: None,
+    labels: &["listener", "status"],
+    description: "HTTP requests served, by listener and status",
+    buckets: &[],
+};
+
+pub const HTTP_REQUEST_DURATION: Metric = Metric {
+    name: "http_request_duration_seconds",
+    kind: MetricKind::Histogram,
+    unit: Some(Unit::Seconds),
+    labels: &["listener"],
+    description: "HTTP request latency, by listener",
+    buckets: LATENCY_BUCKETS,
+};
+
+pub const UPSTREAM_DURATION: Metric = Metric {
+    name: "upstream_request_duration_seconds",
+    kind: MetricKind::Histogram,
+    unit: Some(Unit::Seconds),
+    labels: &["listener"],
+    description: "Time proxied requests waited for the upstream's response headers, by listener",
+    buckets: LATENCY_BUCKETS,
+};
+
+pub const RATE_LIMITED: Metric = Metric {
+    name: "rate_limited_requests_total",
+    kind: MetricKind::Counter,
+    unit: None,
+    labels: &["listener"],
+    description:
+        "Requests refused by --public-rate-limit or --onion-max-concurrent-requests, by listener",
+    buckets: &[],
+};
+
+pub const ARTI_RESTARTS: Metric = Metric {
+    name: "arti_restarts_total",
+    kind: MetricKind::Counter,
+    unit: None,
+    labels: &[],
+    description: "Times arti was relaunched after exiting",
+    buckets: &[],
+};
+
+pub const ONION_DISCOVERY: Metric = Metric {
+    name: "onion_address_discovery_seconds",
+    kind: MetricKind::Gauge,
+    unit: Some(Unit::Seconds),
+    labels: &["service"],
+    description: "Time from startup until the onion address was known, by service",
+    buckets: &[],
+};
+
+pub const ONION_DISCOVERY_STRATEGY: Metric = Metric {
+    name: "onion_address_discoveries_total",
+    kind: MetricKind::Counter,
+    unit: None,
+    labels: &["service", "strategy", "deprecated"],
+    description: "Onion addresses discovered, by service, the strategy that found them, and whether it is deprecated",
+    buckets: &[],
+};
+
+pub const ONION_KNOWN: Metric = Metric {
+    name: "onion_address_known",
+    kind: MetricKind::Gauge,
+    unit: None,
+    labels: &["service"],
+    description: "Whether the onion address is currently known (1) or not (0), by service",
+    buckets: &[],
+};
+
+pub const SHUTDOWNS: Metric = Metric {
+    name: "shutdowns_total",
+    kind: MetricKind::Counter,
+    unit: None,
+    labels: &["reason"],
+    description: "Shutdown requests, by reason; scrapeable while connections drain",
+    buckets: &[],
+};
+
+pub const WARMUP_REQUESTS: Metric = Metric {
+    name: "onion_warmup_requests_total",
+    kind: MetricKind::Counter,
+    unit: None,
+    labels: &["service", "outcome"],
+    description: "Circuit warm-up requests through arti, by service and outcome",
+    buckets: &[],
+};
+
+pub const WARMUP_DURATION: Metric = Metric {
+    name: "onion_warmup_duration_seconds",
+    kind: MetricKind::Histogram,
+    unit: Some(Unit::Seconds),
+    labels: &["service"],
+    description: "Latency of successful circuit warm-up requests, by service",
+    buckets: &[0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0],
+};
+
+pub const CONNECTION_BYTES: Metric = Metric {
+    name: "connection_response_bytes",
+    kind: MetricKind::Histogram,
+    unit: Some(Unit::Bytes),
+    labels: &["listener"],
+    description:
+        "Response body bytes sent over each connection, recorded when it closes, by listener",
+    buckets: &[
+        1024.0,
+        16384.0,
+        131072.0,
+        1048576.0,
+        8388608.0,
+        67108864.0,
+        536870912.0,
+    ],
+};
+
+pub const CONNECTION_THROUGHPUT: Metric = Metric {
+    name: "connection_response_bytes_per_second",
+    kind: MetricKind::Histogram,
+    unit: None,
+    labels: &["listener"],
+    description: "Average rate each connection's response bodies were sent at, recorded when it closes, by listener",
+    buckets: &[
+        1024.0, 8192.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+    ],
+};
+
+/// Every metric the server records.
+pub const ALL: &[Metric] = &[
+    HTTP_REQUESTS,
+    HTTP_REQUEST_DURATION,
+    UPSTREAM_DURATION,
+    RATE_LIMITED,
+    ARTI_RESTARTS,
+    ONION_DISCOVERY,
+    ONION_DISCOVERY_STRATEGY,
+    ONION_KNOWN,
+    SHUTDOWNS,
+    WARMUP_REQUESTS,
+    WARMUP_DURATION,
+    CONNECTION_BYTES,
+    CONNECTION_THROUGHPUT,
+];
+
+/// Describes every metric to the installed recorder, and starts the arti restart count at zero
+/// so it is exported before the first restart.
+pub fn describe() {
+    for metric in ALL {
+        match (metric.kind, metric.unit) {
+            (MetricKind::Counter, Some(unit)) => {
+                metrics::describe_counter!(metric.name, unit, metric.description)
+            }
+            (MetricKind::Counter, None) => {
+                metrics::describe_counter!(metric.name, metric.description)
+            }
+            (MetricKind::Gauge, Some(unit)) => {
+                metrics::describe_gauge!(metric.name, unit, metric.description)
+            }
+            (MetricKind::Gauge, None) => metrics::describe_gauge!(metric.name, metric.description),
+            (MetricKind::Histogram, Some(unit)) => {
+                metrics::describe_histogram!(metric.name, unit, metric.description)
+            }
+            (MetricKind::Histogram, None) => {
+                metrics::describe_histogram!(metric.name, metric.description)
+            }
+        }
+    }
+    metrics::counter!(ARTI_RESTARTS.name).absolute(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_listed_once_and_only_histograms_have_buckets() {
+        let mut names: Vec<_> = ALL.iter().map(|metric| metric.name).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), ALL.len());
+        for metric in ALL {
+            assert!(metric.kind == MetricKind::Histogram || metric.buckets.is_empty());
+        }
+    }
+}
@@ -0,0 +1,18 @@
+// Reloads the landing page once what it waits for, named by the script tag's data attributes,
+// is announced on /events
+const { service, waitAddress, waitBootstrap } = document.currentScript.dataset;
+const events = new EventSource("/events");
+const refresh = () => {
+  events.close();
+  location.reload();
+};
+if (waitAddress !== undefined) {
+  events.addEventListener("onion-address", (event) => {
+    if (JSON.parse(event.data).service === service) refresh();
+  });
+}
+if (waitBootstrap !== undefined) {
+  events.addEventListener("bootstrap", (event) => {
+    if (JSON.parse(event.data).percent === 100) refresh();
+  });
+}